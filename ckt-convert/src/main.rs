@@ -5,7 +5,9 @@ mod parse;
 mod eqn;
 mod dot;
 mod rules;
+mod schedule;
 mod stats;
+mod synth;
 
 /// Convert various circuit formats.
 #[derive(Parser)]
@@ -73,6 +75,31 @@ enum Commands {
     Stats {
         /// Input file to operate on
         infile: PathBuf,
+    },
+    /// Split an eqn network into single gates and levelize them by AND depth into a per-level
+    /// batch schedule
+    Schedule {
+        /// Gates per batched multiplication, to report the number of batches
+        #[arg(long)]
+        slots: Option<usize>,
+        /// Input file to operate on
+        infile: PathBuf,
+        /// Output schedule file
+        outfile: PathBuf,
+        /// Output network with one gate per equation, which the schedule refers to
+        netfile: PathBuf,
+    },
+    /// Enumerate XAG terms and write the rules that lower their AND count or AND depth
+    #[command(name="synth-rules")]
+    SynthRules {
+        /// Number of pattern variables (at most 6)
+        #[arg(long, default_value_t = 3)]
+        vars: usize,
+        /// Largest lhs, in gates (NOT, AND and XOR)
+        #[arg(long, default_value_t = 3)]
+        size: u32,
+        /// Output rules file
+        outfile: PathBuf,
     }
 }
 
@@ -97,6 +124,8 @@ fn main() {
             eqn::eqn2egglog(infile, outfile );
         }
         Commands::Stats { infile } => { stats::file_stats(infile); },
+        Commands::Schedule { slots, infile, outfile, netfile } => { schedule::eqn_schedule(infile, outfile, netfile, slots); },
+        Commands::SynthRules { vars, size, outfile } => { synth::synth_rules(vars, size, outfile); },
         Commands::Sexpr2Eqn { infile, outfile } => {
             eqn::sexpr2eqn(infile, outfile);
         },
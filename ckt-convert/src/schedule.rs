@@ -0,0 +1,148 @@
+use crate::eqn;
+use crate::parse::{Xag, XagOp};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// A single gate (or wire) of a split equation.
+struct Gate {
+    net: String,
+    rhs: String,
+    and: bool,
+    /// Nets the gate reads, without inversions
+    operands: Vec<String>,
+}
+
+/// Appends a gate for every AND and XOR of `x`, inner ones named `<net>_<k>`, and returns the
+/// operand computing `x`.
+fn split(x: &Xag, net: &str, count: &mut usize, gates: &mut Vec<Gate>) -> String {
+    let operand = match x.op.as_ref() {
+        XagOp::And(n1, n2) | XagOp::Xor(n1, n2) => {
+            let (a, b) = (split(n1, net, count, gates), split(n2, net, count, gates));
+            let and = matches!(x.op.as_ref(), XagOp::And(..));
+            *count += 1;
+            let name = format!("{}_{}", net, count);
+            gates.push(Gate {
+                net: name.clone(),
+                rhs: format!("{} {} {};", a, if and { "*" } else { "^" }, b),
+                and,
+                operands: [a, b].iter().map(|o| o.trim_start_matches('!').to_string()).collect(),
+            });
+            name
+        }
+        XagOp::Ident(i) => i.clone(),
+        XagOp::Lit(b) => b.to_string(),
+        XagOp::Concat(_) => unreachable!("eqn equations have no concatenation"),
+    };
+    if x.inv { format!("!{}", operand) } else { operand }
+}
+
+/// Appends the gates of the equation `net = x`, the last one computing `net`.
+fn split_equation(net: &str, x: &Xag, gates: &mut Vec<Gate>) {
+    let first = gates.len();
+    let operand = split(x, net, &mut 0, gates);
+    if gates.len() > first && !x.inv {
+        gates.last_mut().unwrap().net = net.to_string();
+    } else {
+        gates.push(Gate {
+            net: net.to_string(),
+            rhs: format!("{};", operand),
+            and: false,
+            operands: vec![operand.trim_start_matches('!').to_string()],
+        });
+    }
+}
+
+/// The ANDs (`MUL`) and other gates (`LIN`) on each level, in network order.
+fn levelize(gates: &[Gate]) -> (Vec<Vec<&str>>, Vec<Vec<&str>>) {
+    let mut depth: HashMap<&str, usize> = HashMap::new();
+    let mut mul: Vec<Vec<&str>> = vec![Vec::new()];
+    let mut lin: Vec<Vec<&str>> = vec![Vec::new()];
+    for gate in gates {
+        // inputs and constants are at level 0
+        let level = gate.operands.iter().map(|o| *depth.get(o.as_str()).unwrap_or(&0)).max().unwrap() + gate.and as usize;
+        if mul.len() <= level {
+            mul.resize(level + 1, Vec::new());
+            lin.resize(level + 1, Vec::new());
+        }
+        let batch = if gate.and { &mut mul[level] } else { &mut lin[level] };
+        batch.push(&gate.net);
+        depth.insert(&gate.net, level);
+    }
+    (mul, lin)
+}
+
+/// Splits every equation of an eqn network into single gates and levelizes them by AND depth
+/// for batched (SIMD) evaluation.
+///
+/// The split network goes to `netfile`: the gates inside an equation for `net` are named
+/// `<net>_1`, `<net>_2`, ..., and its top gate keeps the name `net`. Level `l` of the schedule is
+/// evaluated as one batch of the ANDs that land on level `l` (`MUL l`), followed by the linear
+/// gates and wires whose value is at level `l` (`LIN l`), in network order. With `slots`, a
+/// level of `w` ANDs takes `ceil(w / slots)` batched multiplications.
+pub fn eqn_schedule(infile: PathBuf, outfile: PathBuf, netfile: PathBuf, slots: Option<usize>) {
+    let lines = std::fs::read_to_string(infile).unwrap();
+    let eqn = eqn::parse_eqn(&lines);
+    let mut gates: Vec<Gate> = Vec::new();
+    for net in &eqn.lhses {
+        split_equation(net, eqn.equations.get(net).unwrap(), &mut gates);
+    }
+    let (mul, lin) = levelize(&gates);
+
+    let mut network = format!("INORDER = {};\nOUTORDER = {};\n", eqn.innodes.join(" "), eqn.outnodes.join(" "));
+    for gate in &gates {
+        network.push_str(format!("{} = {}\n", gate.net, gate.rhs).as_str());
+    }
+    std::fs::write(netfile, network).unwrap();
+
+    let mut contents = format!("LEVELS = {};\n", mul.len() - 1);
+    for level in 0..mul.len() {
+        if !mul[level].is_empty() {
+            contents.push_str(format!("MUL {} = {};\n", level, mul[level].join(" ")).as_str());
+        }
+        if !lin[level].is_empty() {
+            contents.push_str(format!("LIN {} = {};\n", level, lin[level].join(" ")).as_str());
+        }
+    }
+    std::fs::write(outfile, contents).unwrap();
+
+    let widths: Vec<usize> = mul.iter().map(|m| m.len()).collect();
+    for (level, width) in widths.iter().enumerate().skip(1) {
+        println!("level {}: {} ANDs", level, width);
+    }
+    let non_empty = widths.iter().filter(|w| **w > 0).count();
+    println!("non-empty levels = {}; max width = {}", non_empty, widths.iter().max().unwrap());
+    if let Some(slots) = slots {
+        let batches: usize = widths.iter().map(|w| w.div_ceil(slots)).sum();
+        println!("batched multiplications = {} ({} slots)", batches, slots);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::infix_to_xag;
+
+    fn schedule(equations: &[(&str, &str)]) -> (Vec<Vec<String>>, Vec<Vec<String>>) {
+        let mut gates = Vec::new();
+        for (net, rhs) in equations {
+            split_equation(net, &infix_to_xag(rhs), &mut gates);
+        }
+        let (mul, lin) = levelize(&gates);
+        let owned = |v: Vec<Vec<&str>>| v.into_iter().map(|l| l.into_iter().map(String::from).collect()).collect();
+        (owned(mul), owned(lin))
+    }
+
+    #[test]
+    fn stacked_ands_get_their_own_levels() {
+        let (mul, lin) = schedule(&[("n", "(a * b) * (c * d)")]);
+        assert_eq!(mul, vec![vec![], vec!["n_1", "n_2"], vec!["n"]]);
+        assert!(lin.iter().all(|l| l.is_empty()));
+    }
+
+    #[test]
+    fn linear_gates_and_wires_follow_their_operands() {
+        let (mul, lin) = schedule(&[("x", "a * b"), ("y", "x ^ c"), ("o", "!y")]);
+        assert_eq!(mul, vec![vec![], vec!["x"]]);
+        assert_eq!(lin, vec![vec![], vec!["y", "o"]]);
+    }
+}
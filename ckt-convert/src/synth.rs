@@ -0,0 +1,372 @@
+// Enumerative rule synthesis in the spirit of Ruler: XAG terms up to a size over a few pattern
+// variables are grouped by truth table, and a term gets a rule to the cheapest term of its group
+// when that one has fewer ANDs (tag `mc`) or a lower AND depth (tag `md`). As in Ruler, larger
+// terms are only built from one representative term per group.
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+const VAR_NAMES: [&str; 6] = ["x", "y", "z", "w", "v", "u"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Var(usize),
+    Const(bool),
+    Not(usize),
+    And(usize, usize),
+    Xor(usize, usize),
+}
+
+#[derive(Clone, Copy)]
+struct Term {
+    op: Op,
+    /// Truth table over all variables, one bit per assignment
+    tt: u64,
+    /// Variables the term mentions
+    vars: u8,
+    mc: u32,
+    md: u32,
+    size: u32,
+}
+
+#[derive(Clone, Copy)]
+enum Objective {
+    Mc,
+    Md,
+}
+
+impl Objective {
+    fn tag(self) -> &'static str {
+        match self {
+            Objective::Mc => "mc",
+            Objective::Md => "md",
+        }
+    }
+
+    /// Ordering key, most important first
+    fn key(self, t: &Term) -> (u32, u32, u32) {
+        match self {
+            Objective::Mc => (t.mc, t.md, t.size),
+            Objective::Md => (t.md, t.mc, t.size),
+        }
+    }
+
+    fn improves(self, rhs: &Term, lhs: &Term) -> bool {
+        match self {
+            Objective::Mc => rhs.mc < lhs.mc && rhs.md <= lhs.md,
+            Objective::Md => rhs.md < lhs.md && rhs.mc <= lhs.mc,
+        }
+    }
+}
+
+struct Synth {
+    vars: usize,
+    terms: Vec<Term>,
+    /// (truth table, variables) -> cheapest term, per objective
+    best: [HashMap<(u64, u8), usize>; 2],
+}
+
+impl Synth {
+    fn mask(&self) -> u64 {
+        if self.vars == 6 { !0 } else { (1u64 << (1 << self.vars)) - 1 }
+    }
+
+    fn var_tt(&self, i: usize) -> u64 {
+        (0..1u64 << self.vars).filter(|a| (a >> i) & 1 == 1).fold(0, |tt, a| tt | (1 << a))
+    }
+
+    fn add(&mut self, op: Op) -> usize {
+        let t = |i: usize| self.terms[i];
+        let term = match op {
+            Op::Var(i) => Term { op, tt: self.var_tt(i), vars: 1 << i, mc: 0, md: 0, size: 0 },
+            Op::Const(b) => Term { op, tt: if b { self.mask() } else { 0 }, vars: 0, mc: 0, md: 0, size: 0 },
+            Op::Not(a) => Term { op, tt: !t(a).tt & self.mask(), size: t(a).size + 1, ..t(a) },
+            Op::And(a, b) | Op::Xor(a, b) => {
+                let and = matches!(op, Op::And(..)) as u32;
+                Term {
+                    op,
+                    tt: if and == 1 { t(a).tt & t(b).tt } else { t(a).tt ^ t(b).tt },
+                    vars: t(a).vars | t(b).vars,
+                    mc: t(a).mc + t(b).mc + and,
+                    md: t(a).md.max(t(b).md) + and,
+                    size: t(a).size + t(b).size + 1,
+                }
+            }
+        };
+        let id = self.terms.len();
+        self.terms.push(term);
+        for (o, objective) in [Objective::Mc, Objective::Md].into_iter().enumerate() {
+            let key = (term.tt, term.vars);
+            match self.best[o].get(&key) {
+                Some(b) if objective.key(&self.terms[*b]) <= objective.key(&term) => {}
+                _ => {
+                    self.best[o].insert(key, id);
+                }
+            }
+        }
+        id
+    }
+
+    /// Cheapest term equal to `lhs` that only uses variables of `lhs`.
+    fn cheapest(&self, lhs: usize, objective: Objective) -> usize {
+        let l = self.terms[lhs];
+        let o = objective as usize;
+        (0..=l.vars)
+            .filter(|v| v & !l.vars == 0)
+            .filter_map(|v| self.best[o].get(&(l.tt, v)).copied())
+            .min_by_key(|b| objective.key(&self.terms[*b]))
+            .unwrap()
+    }
+
+    /// Whether `id` is the cheapest term of its group under some objective so far.
+    fn representative(&self, id: usize) -> bool {
+        let key = (self.terms[id].tt, self.terms[id].vars);
+        self.best.iter().any(|best| best[&key] == id)
+    }
+
+    fn children(&self, id: usize) -> Vec<usize> {
+        match self.terms[id].op {
+            Op::Var(_) | Op::Const(_) => vec![],
+            Op::Not(a) => vec![a],
+            Op::And(a, b) | Op::Xor(a, b) => vec![a, b],
+        }
+    }
+
+    /// Variables in order of first occurrence.
+    fn var_order(&self, id: usize, order: &mut Vec<usize>) {
+        if let Op::Var(i) = self.terms[id].op {
+            if !order.contains(&i) {
+                order.push(i);
+            }
+        }
+        for c in self.children(id) {
+            self.var_order(c, order);
+        }
+    }
+
+    /// Rule-file patterns of `id`, with the operands of every AND and XOR in both orders, the
+    /// enumerated order first. Variable `i` is written `?i`.
+    fn orders(&self, id: usize) -> Vec<String> {
+        match self.terms[id].op {
+            Op::Var(i) => vec![format!("?{}", i)],
+            Op::Const(b) => vec![b.to_string()],
+            Op::Not(a) => self.orders(a).iter().map(|p| format!("(! {})", p)).collect(),
+            Op::And(a, b) | Op::Xor(a, b) => {
+                let op = if matches!(self.terms[id].op, Op::And(..)) { "*" } else { "^" };
+                let (pa, pb) = (self.orders(a), self.orders(b));
+                let mut patterns = Vec::new();
+                for x in &pa {
+                    for y in &pb {
+                        patterns.push(format!("({} {} {})", op, x, y));
+                        patterns.push(format!("({} {} {})", op, y, x));
+                    }
+                }
+                patterns
+            }
+        }
+    }
+
+    fn eval(&self, id: usize, assignment: u64) -> bool {
+        match self.terms[id].op {
+            Op::Var(i) => (assignment >> i) & 1 == 1,
+            Op::Const(b) => b,
+            Op::Not(a) => !self.eval(a, assignment),
+            Op::And(a, b) => self.eval(a, assignment) && self.eval(b, assignment),
+            Op::Xor(a, b) => self.eval(a, assignment) ^ self.eval(b, assignment),
+        }
+    }
+
+    /// Checks the rule by evaluating both sides on every assignment.
+    fn verify(&self, lhs: usize, rhs: usize) -> bool {
+        (0..1u64 << self.vars).all(|a| self.eval(lhs, a) == self.eval(rhs, a))
+    }
+}
+
+/// `lhs` and `rhs` with the variables `?i` renamed to `?x`, `?y`, ... in order of first
+/// occurrence in `lhs`.
+fn rename(lhs: &str, rhs: &str) -> (String, String) {
+    let mut order: Vec<char> = Vec::new();
+    for v in lhs.split('?').skip(1).filter_map(|p| p.chars().next()) {
+        if !order.contains(&v) {
+            order.push(v);
+        }
+    }
+    let rename = |pattern: &str| {
+        let mut renamed = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            renamed.push(c);
+            if c == '?' {
+                let v = chars.next().unwrap();
+                renamed.push_str(VAR_NAMES[order.iter().position(|o| *o == v).unwrap()]);
+            }
+        }
+        renamed
+    };
+    (rename(lhs), rename(rhs))
+}
+
+/// The rules for every term of at most `size` gates over `vars` variables that has a cheaper
+/// equivalent, and the number of terms enumerated. Rules whose lhs has an operand with a cheaper
+/// equivalent are left out, as are lhs that only differ by renaming variables. Every rule is
+/// written with the operands of its lhs in every order, since the rules may be used without
+/// commutative matching; the other orders are tagged `commuted`.
+fn synthesize(vars: usize, size: u32) -> (usize, Vec<String>) {
+    assert!((1..=VAR_NAMES.len()).contains(&vars), "between 1 and {} variables", VAR_NAMES.len());
+    let mut synth = Synth { vars, terms: Vec::new(), best: [HashMap::new(), HashMap::new()] };
+    synth.add(Op::Const(false));
+    synth.add(Op::Const(true));
+
+    let mut by_size: Vec<Vec<usize>> = vec![(0..vars).map(|i| synth.add(Op::Var(i))).collect()];
+    for s in 1..=size as usize {
+        let mut level = Vec::new();
+        for a in by_size[s - 1].clone() {
+            // double negations are never cheaper
+            if !matches!(synth.terms[a].op, Op::Not(_)) {
+                level.push(synth.add(Op::Not(a)));
+            }
+        }
+        for sa in 0..s {
+            let sb = s - 1 - sa;
+            if sa > sb {
+                break;
+            }
+            for (i, a) in by_size[sa].clone().into_iter().enumerate() {
+                let bs = if sa == sb { by_size[sb][i..].to_vec() } else { by_size[sb].clone() };
+                for b in bs {
+                    level.push(synth.add(Op::And(a, b)));
+                    level.push(synth.add(Op::Xor(a, b)));
+                }
+            }
+        }
+        // only the cheapest term of a group is an operand of larger terms; the others can still
+        // be an lhs
+        level.retain(|id| synth.representative(*id));
+        by_size.push(level);
+    }
+
+    // (lhs, rhs) -> objectives it helps, in the order found
+    let mut rules: Vec<((usize, usize), Vec<Objective>)> = Vec::new();
+    let mut index: HashMap<(usize, usize), usize> = HashMap::new();
+    for objective in [Objective::Mc, Objective::Md] {
+        // terms that are the cheapest of their group; only these appear in an lhs below the root
+        let mut normal = vec![true; synth.terms.len()];
+        for id in 0..synth.terms.len() {
+            let rhs = synth.cheapest(id, objective);
+            normal[id] = objective.key(&synth.terms[rhs]) >= objective.key(&synth.terms[id]);
+            if !objective.improves(&synth.terms[rhs], &synth.terms[id]) || synth.children(id).iter().any(|c| !normal[*c]) {
+                continue;
+            }
+            let mut order = Vec::new();
+            synth.var_order(id, &mut order);
+            if order.iter().enumerate().any(|(i, v)| i != *v) {
+                continue;
+            }
+            assert!(synth.verify(id, rhs), "synthesized rule does not hold: {:?}", rename(&synth.orders(id)[0], &synth.orders(rhs)[0]));
+            let i = *index.entry((id, rhs)).or_insert_with(|| {
+                rules.push(((id, rhs), Vec::new()));
+                rules.len() - 1
+            });
+            rules[i].1.push(objective);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut lhses: HashSet<String> = HashSet::new();
+    for (i, ((lhs, rhs), objectives)) in rules.iter().enumerate() {
+        let (l, r) = (synth.terms[*lhs], synth.terms[*rhs]);
+        let tags: Vec<&str> = objectives.iter().map(|o| o.tag()).collect();
+        let rhs = &synth.orders(*rhs)[0];
+        for (j, lhs) in synth.orders(*lhs).iter().enumerate() {
+            let (lhs, rhs) = rename(lhs, rhs);
+            if !lhses.insert(lhs.clone()) {
+                continue;
+            }
+            let (name, tags) = if j == 0 { (format!("syn{}", i), tags.join(",")) } else { (format!("syn{}-{}", i, j), tags.join(",") + ",commuted") };
+            lines.push(format!("{}[{}]:{}=>{}  # mc {}->{}, md {}->{}", name, tags, lhs, rhs, l.mc, r.mc, l.md, r.md));
+        }
+    }
+    (synth.terms.len(), lines)
+}
+
+/// Writes the rules of [`synthesize`] to `outfile`. Rules tagged `commuted` can be disabled
+/// when saturating with commutative matching.
+pub fn synth_rules(vars: usize, size: u32, outfile: PathBuf) {
+    let (terms, lines) = synthesize(vars, size);
+    let mut out = format!("# synthesized by ckt-convert synth-rules --vars {} --size {}\n", vars, size);
+    for line in &lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+    println!("{} terms, {} rules", terms, lines.len());
+    std::fs::write(outfile, out).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Value of the rule-file pattern at the front of `tokens` under `assignment`.
+    fn eval(tokens: &mut std::slice::Iter<&str>, assignment: u64) -> bool {
+        match *tokens.next().unwrap() {
+            "(" => {
+                let op = *tokens.next().unwrap();
+                let a = eval(tokens, assignment);
+                let value = match op {
+                    "!" => !a,
+                    "*" => a & eval(tokens, assignment),
+                    "^" => a ^ eval(tokens, assignment),
+                    _ => panic!("unknown operator {}", op),
+                };
+                assert_eq!(tokens.next(), Some(&")"));
+                value
+            }
+            "true" => true,
+            "false" => false,
+            v => (assignment >> VAR_NAMES.iter().position(|n| *n == &v[1..]).unwrap()) & 1 == 1,
+        }
+    }
+
+    fn holds(rule: &str) -> bool {
+        let (lhs, rhs) = rule.split_once(':').unwrap().1.split_once("  #").unwrap().0.split_once("=>").unwrap();
+        let spaced = |p: &str| p.replace('(', "( ").replace(')', " )");
+        let (lhs, rhs) = (spaced(lhs), spaced(rhs));
+        let (lhs, rhs): (Vec<&str>, Vec<&str>) = (lhs.split_whitespace().collect(), rhs.split_whitespace().collect());
+        (0..1u64 << VAR_NAMES.len()).all(|a| eval(&mut lhs.iter(), a) == eval(&mut rhs.iter(), a))
+    }
+
+    fn rule<'a>(lines: &'a [String], pattern: &str) -> Option<&'a String> {
+        lines.iter().find(|l| l.contains(&format!(":{}  #", pattern)))
+    }
+
+    #[test]
+    fn synthesized_rules_hold() {
+        let (_, lines) = synthesize(3, 3);
+        assert!(!lines.is_empty());
+        for line in &lines {
+            assert!(holds(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn every_operand_order_is_written() {
+        let (_, lines) = synthesize(2, 2);
+        let rule = |pattern| rule(&lines, pattern).unwrap_or_else(|| panic!("no rule {}", pattern));
+        assert!(!rule("(* ?x (! ?x))=>false").contains("commuted"));
+        assert!(rule("(* (! ?x) ?x)=>false").contains("commuted"));
+        assert!(rule("(* ?x (* ?y ?x))=>(* ?x ?y)").contains("commuted"));
+        assert!(rule("(* (* ?x ?y) ?y)=>(* ?y ?x)").contains("commuted"));
+        // the same lhs is only written once
+        let mut lhses: Vec<&str> = lines.iter().map(|l| l.split_once(':').unwrap().1.split_once("=>").unwrap().0).collect();
+        let count = lhses.len();
+        lhses.sort();
+        lhses.dedup();
+        assert_eq!(lhses.len(), count);
+    }
+
+    #[test]
+    fn larger_terms_only_use_representatives() {
+        // enumerating every term gives 10628 terms for 3 variables and 4 gates
+        let (terms, _) = synthesize(3, 4);
+        assert!(terms < 2000, "{} terms", terms);
+    }
+}
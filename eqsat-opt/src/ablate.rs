@@ -0,0 +1,135 @@
+// Rule-set minimization: the smallest subset of a rule set that still reaches the same
+// MD and MC on every benchmark, found by greedy removal or delta debugging.
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Method {
+    /// Drop rules one at a time, keeping each removal that still passes
+    #[default]
+    Greedy,
+    /// Zeller's ddmin: drop ever smaller chunks of rules; far fewer runs when most rules are unneeded
+    DeltaDebug,
+}
+
+/// Remembers which subsets passed, so no subset is run twice.
+struct Oracle<F: FnMut(&[usize]) -> bool> {
+    passes: F,
+    seen: HashMap<Vec<usize>, bool>,
+}
+
+impl<F: FnMut(&[usize]) -> bool> Oracle<F> {
+    fn test(&mut self, subset: &[usize]) -> bool {
+        if let Some(result) = self.seen.get(subset) {
+            return *result;
+        }
+        let result = (self.passes)(subset);
+        println!("{} rules: {}", subset.len(), if result { "pass" } else { "fail" });
+        self.seen.insert(subset.to_vec(), result);
+        result
+    }
+}
+
+fn without(set: &[usize], removed: &[usize]) -> Vec<usize> {
+    set.iter().copied().filter(|i| !removed.contains(i)).collect()
+}
+
+fn greedy<F: FnMut(&[usize]) -> bool>(oracle: &mut Oracle<F>, n: usize) -> Vec<usize> {
+    let mut keep: Vec<usize> = (0..n).collect();
+    for i in 0..n {
+        let candidate = without(&keep, &[i]);
+        if oracle.test(&candidate) {
+            keep = candidate;
+        }
+    }
+    keep
+}
+
+fn delta_debug<F: FnMut(&[usize]) -> bool>(oracle: &mut Oracle<F>, n: usize) -> Vec<usize> {
+    let mut keep: Vec<usize> = (0..n).collect();
+    let mut granularity = 2;
+    while keep.len() >= 2 {
+        let chunk_len = keep.len().div_ceil(granularity);
+        let chunks: Vec<Vec<usize>> = keep.chunks(chunk_len).map(|c| c.to_vec()).collect();
+        if let Some(chunk) = chunks.iter().find(|c| oracle.test(c)) {
+            keep = chunk.clone();
+            granularity = 2;
+        } else if let Some(rest) = chunks.iter().map(|c| without(&keep, c)).find(|r| oracle.test(r)) {
+            keep = rest;
+            granularity = (granularity - 1).max(2);
+        } else if granularity < keep.len() {
+            granularity = (granularity * 2).min(keep.len());
+        } else {
+            // no single rule can be dropped
+            break;
+        }
+    }
+    keep
+}
+
+/// Indices of a minimal subset of `n` rules for which `passes` holds; the full set must pass.
+/// The result is 1-minimal: dropping any one of its rules fails.
+pub fn minimize(n: usize, method: Method, passes: impl FnMut(&[usize]) -> bool) -> Vec<usize> {
+    let mut oracle = Oracle { passes, seen: HashMap::new() };
+    if oracle.test(&[]) {
+        return Vec::new();
+    }
+    match method {
+        Method::Greedy => greedy(&mut oracle, n),
+        Method::DeltaDebug => delta_debug(&mut oracle, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimizes 8 rules for an oracle that needs rules 2 and 5, counting the runs.
+    fn essential(method: Method) -> (Vec<usize>, Vec<Vec<usize>>) {
+        let mut runs: Vec<Vec<usize>> = Vec::new();
+        let kept = minimize(8, method, |subset| {
+            runs.push(subset.to_vec());
+            subset.contains(&2) && subset.contains(&5)
+        });
+        (kept, runs)
+    }
+
+    #[test]
+    fn greedy_keeps_exactly_the_needed_rules() {
+        let (kept, runs) = essential(Method::Greedy);
+        assert_eq!(kept, [2, 5]);
+        // the empty set, then one removal per rule
+        assert_eq!(runs.len(), 9);
+    }
+
+    #[test]
+    fn delta_debugging_keeps_exactly_the_needed_rules() {
+        let (kept, runs) = essential(Method::DeltaDebug);
+        assert_eq!(kept, [2, 5]);
+        let mut unique = runs.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), runs.len(), "a subset was run twice");
+    }
+
+    #[test]
+    fn nothing_is_kept_when_no_rule_is_needed() {
+        for method in [Method::Greedy, Method::DeltaDebug] {
+            assert!(minimize(5, method, |_| true).is_empty());
+        }
+    }
+
+    #[test]
+    fn results_are_one_minimal() {
+        // passes with rule 0, or with both 1 and 3
+        let passes = |s: &[usize]| s.contains(&0) || (s.contains(&1) && s.contains(&3));
+        for method in [Method::Greedy, Method::DeltaDebug] {
+            let kept = minimize(6, method, passes);
+            assert!(passes(&kept));
+            for i in &kept {
+                assert!(!passes(&without(&kept, &[*i])), "{:?} keeps {} needlessly", method, i);
+            }
+        }
+    }
+}
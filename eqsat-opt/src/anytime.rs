@@ -0,0 +1,71 @@
+// Best-so-far network, written to the output file on SIGINT/SIGTERM or when the time budget runs out.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+struct State {
+    /// Best network offered so far, with its score (lower is better)
+    best: Option<(f64, String)>,
+    /// The run finished and wrote its own result
+    done: bool,
+}
+
+pub struct Incumbent {
+    state: Mutex<State>,
+    outfile: PathBuf,
+    innodes: String,
+    outnodes: String,
+}
+
+impl Incumbent {
+    pub fn new(outfile: PathBuf, innodes: &str, outnodes: &str) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(State { best: None, done: false }),
+            outfile,
+            innodes: innodes.to_string(),
+            outnodes: outnodes.to_string(),
+        })
+    }
+
+    /// Keeps `network` if it scores better than the current best.
+    pub fn offer(&self, score: f64, network: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.best.as_ref().is_none_or(|(best, _)| score < *best) {
+            state.best = Some((score, network.to_string()));
+        }
+    }
+
+    /// Writes the run's final network; interrupts after this point leave the output alone.
+    pub fn finish(&self, network: &str) {
+        let mut state = self.state.lock().unwrap();
+        crate::write_network(&self.outfile, &self.innodes, &self.outnodes, network);
+        state.done = true;
+    }
+
+    fn bail(&self, reason: &str, code: i32) -> ! {
+        let state = self.state.lock().unwrap();
+        if !state.done {
+            match &state.best {
+                Some((score, network)) => {
+                    println!("{}: writing best network so far (score = {})", reason, score);
+                    crate::write_network(&self.outfile, &self.innodes, &self.outnodes, network);
+                }
+                None => println!("{}: no network extracted yet", reason),
+            }
+        }
+        std::process::exit(code);
+    }
+
+    /// Writes the best network and exits on SIGINT/SIGTERM, and at `deadline` if there is one.
+    pub fn install_handlers(self: &Arc<Self>, deadline: Option<Instant>) {
+        let incumbent = Arc::clone(self);
+        ctrlc::set_handler(move || incumbent.bail("interrupted", 130)).unwrap();
+        if let Some(deadline) = deadline {
+            let incumbent = Arc::clone(self);
+            std::thread::spawn(move || {
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                incumbent.bail("time budget expired", 0);
+            });
+        }
+    }
+}
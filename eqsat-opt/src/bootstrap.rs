@@ -0,0 +1,201 @@
+// Bootstrap-aware extraction for leveled schemes (BGV/BFV/CKKS) with a fixed level budget.
+// `extract` picks e-nodes by gate cost plus the bootstraps their operands need, and
+// `place_bootstraps` then places the bootstraps on the extracted network. Both are greedy, so
+// this is a heuristic with no optimality guarantee.
+use std::collections::{HashMap, HashSet};
+
+use egraph_serialize::{ClassId, EGraph, NodeId};
+
+use crate::cost_model::{HeCostModel, NetworkCost};
+use crate::extraction_ser::{self, ExtractionResult};
+
+pub struct BootstrapPlan {
+    /// Classes whose value is refreshed to level 0 right after it is computed
+    pub locations: Vec<ClassId>,
+    /// Gate cost of the network plus the weighted bootstrap cost
+    pub total_cost: f64,
+}
+
+impl BootstrapPlan {
+    /// `network` with a `# bootstrap n<class>` line after the equation of every refreshed net.
+    pub fn annotate(&self, network: &str) -> String {
+        let mut annotated = String::new();
+        for line in network.lines() {
+            annotated.push_str(line);
+            annotated.push('\n');
+            let net = line.split_once(" = ").map(|(lhs, _)| lhs);
+            if self.locations.iter().any(|c| net == Some(format!("n{}", c).as_str())) {
+                annotated.push_str(&format!("# bootstrap {}\n", net.unwrap()));
+            }
+        }
+        annotated
+    }
+}
+
+/// Greedy extraction under a level budget: every class takes the node with the least gate cost
+/// plus bootstrap cost below it, an operand being bootstrapped (for `bootstrap_cost`) whenever
+/// the gate consuming it would go past `level_budget`. Costs are summed as on a tree, so a
+/// shared operand counts once per use. `None` if a root has no node within the budget.
+pub fn extract(egraph: &EGraph, roots: &[ClassId], model: &dyn HeCostModel, level_budget: usize, bootstrap_cost: f64) -> Option<ExtractionResult> {
+    // class -> (cost, level, node)
+    let mut best: HashMap<ClassId, (f64, usize, NodeId)> = HashMap::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (node_id, node) in &egraph.nodes {
+            let node_depth = model.node_depth(egraph, node);
+            if node_depth > level_budget {
+                continue;
+            }
+            let Some(children) = node.children.iter().map(|c| best.get(egraph.nid_to_cid(c))).collect::<Option<Vec<_>>>() else { continue };
+            let (mut cost, mut level) = (model.node_cost(egraph, node), node_depth);
+            for (child_cost, child_level, _) in children {
+                cost += child_cost;
+                if child_level + node_depth > level_budget {
+                    cost += bootstrap_cost;
+                } else {
+                    level = level.max(child_level + node_depth);
+                }
+            }
+            if best.get(&node.eclass).is_none_or(|(c, l, _)| (cost, level) < (*c, *l)) {
+                best.insert(node.eclass, (cost, level, *node_id));
+                changed = true;
+            }
+        }
+    }
+    let choices: HashMap<ClassId, NodeId> = best.into_iter().map(|(class, (_, _, node))| (class, node)).collect();
+    extraction_ser::levelize(egraph, roots, &choices, model).map(|(_, _, result)| result)
+}
+
+/// Classes of `result` ordered so that every class comes after its children.
+fn topological(egraph: &EGraph, result: &ExtractionResult) -> Vec<ClassId> {
+    let mut order: Vec<ClassId> = Vec::new();
+    let mut done: HashSet<ClassId> = HashSet::new();
+    for root in result.keys() {
+        let mut stack: Vec<(ClassId, bool)> = vec![(*root, false)];
+        while let Some((class, expanded)) = stack.pop() {
+            if done.contains(&class) {
+                continue;
+            }
+            if expanded {
+                done.insert(class);
+                order.push(class);
+                continue;
+            }
+            stack.push((class, true));
+            let node = &egraph[&result[&class].1];
+            stack.extend(node.children.iter().map(|c| (ClassId::new(c.class()), false)));
+        }
+    }
+    order
+}
+
+/// Places bootstraps on an extracted network so that no value goes past `level_budget` levels.
+///
+/// Placement is lazy: a value is only bootstrapped once a gate consuming it would exceed the budget,
+/// and the refreshed value is then shared by every later consumer. This is greedy and need not
+/// place the fewest bootstraps.
+/// Returns `None` if a single gate needs more levels than the budget allows.
+pub fn place_bootstraps(
+    egraph: &EGraph,
+    result: &ExtractionResult,
+    net_cost: &NetworkCost,
+    model: &dyn HeCostModel,
+    level_budget: usize,
+    bootstrap_cost: f64,
+) -> Option<BootstrapPlan> {
+    let mut levels: HashMap<ClassId, usize> = HashMap::new();
+    let mut locations: Vec<ClassId> = Vec::new();
+
+    for class in topological(egraph, result) {
+        let node = &egraph[&result[&class].1];
+        let node_depth = model.node_depth(egraph, node);
+        if node_depth > level_budget {
+            return None;
+        }
+        let children: Vec<ClassId> = node.children.iter().map(|c| ClassId::new(c.class())).collect();
+        for child in &children {
+            if levels[child] + node_depth > level_budget {
+                levels.insert(*child, 0);
+                locations.push(*child);
+            }
+        }
+        let level = children.iter().map(|c| levels[c]).max().unwrap_or(0) + node_depth;
+        levels.insert(class, level);
+    }
+
+    Some(BootstrapPlan {
+        total_cost: net_cost.cost + bootstrap_cost * locations.len() as f64,
+        locations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Prop, PropAnalysis};
+    use crate::cost_model::LeveledBgv;
+    use crate::{global_greedy_dag, serde};
+    use egg::RecExpr;
+
+    /// The e-graph of `exprs`, with the roots as classes, and the class of every expression.
+    fn egraph(exprs: &[&str]) -> (EGraph, Vec<ClassId>) {
+        let mut egraph: egg::EGraph<Prop, PropAnalysis> = Default::default();
+        let ids: Vec<egg::Id> = exprs.iter().map(|e| egraph.add_expr(&e.parse::<RecExpr<Prop>>().unwrap())).collect();
+        egraph.rebuild();
+        let classes = ids.iter().map(|id| ClassId::new(u32::from(egraph.find(*id)))).collect();
+        (serde::serialize_in_mem(&egraph, &ids, &LeveledBgv), classes)
+    }
+
+    /// Bootstraps placed on the greedy extraction of the roots `exprs[..roots]`.
+    fn place(exprs: &[&str], roots: usize, level_budget: usize) -> (Option<BootstrapPlan>, Vec<ClassId>) {
+        let (egraph, classes) = egraph(exprs);
+        let result = global_greedy_dag::mc_extract(&egraph, &classes[..roots], HashMap::new(), &LeveledBgv);
+        let (_, _, result) = extraction_ser::levelize(&egraph, &classes[..roots], &result.iter().map(|(c, (_, n))| (*c, *n)).collect(), &LeveledBgv).unwrap();
+        let net_cost = NetworkCost { cost: 3.0, ..NetworkCost::default() };
+        (place_bootstraps(&egraph, &result, &net_cost, &LeveledBgv, level_budget, 10.0), classes)
+    }
+
+    #[test]
+    fn a_value_past_the_budget_is_refreshed() {
+        let (plan, classes) = place(&["(* (* (* a b) c) d)", "(* (* a b) c)"], 1, 2);
+        let plan = plan.unwrap();
+        assert_eq!(plan.locations, vec![classes[1]]);
+        assert_eq!(plan.total_cost, 13.0);
+    }
+
+    #[test]
+    fn a_shared_value_is_refreshed_once() {
+        let (plan, classes) = place(&["(* (* (* a b) c) d)", "(* (* (* a b) c) e)", "(* (* a b) c)"], 2, 2);
+        assert_eq!(plan.unwrap().locations, vec![classes[2]]);
+    }
+
+    #[test]
+    fn a_gate_deeper_than_the_budget_is_infeasible() {
+        assert!(place(&["(* a b)"], 1, 0).0.is_none());
+    }
+
+    #[test]
+    fn extraction_prices_bootstraps() {
+        // four ANDs on four levels, or five on three
+        let mut egraph: egg::EGraph<Prop, PropAnalysis> = Default::default();
+        let deep = egraph.add_expr(&"(* (* (* (* a b) c) d) e)".parse().unwrap());
+        let shallow = egraph.add_expr(&"(* (* (* a b) (* c c)) (* d e))".parse().unwrap());
+        egraph.union(deep, shallow);
+        egraph.rebuild();
+        let deep = egraph.find(deep);
+        let egraph = serde::serialize_in_mem(&egraph, [&deep], &LeveledBgv);
+        let root = ClassId::new(u32::from(deep));
+        let md = |level_budget| extract(&egraph, &[root], &LeveledBgv, level_budget, 10.0).map(|r| r[&root].0);
+        assert_eq!(md(4), Some(4));
+        assert_eq!(md(3), Some(3));
+        assert_eq!(md(0), None);
+    }
+
+    #[test]
+    fn refreshed_nets_are_annotated_after_their_equation() {
+        let plan = BootstrapPlan { locations: vec![ClassId::new(3)], total_cost: 0.0 };
+        let network = "n3 = a * b;\nn4 = n3 * c;\nn33 = n4 * n3;\no = n33;\n";
+        assert_eq!(plan.annotate(network), "n3 = a * b;\n# bootstrap n3\nn4 = n3 * c;\nn33 = n4 * n3;\no = n33;\n");
+    }
+}
@@ -3,6 +3,14 @@ use egg::*;
 use std::usize::MAX;
 use std::f64::INFINITY;
 use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::cost_model::{HeCostModel, LeveledBgv};
 
 define_language! {
     pub enum Prop {
@@ -20,8 +28,199 @@ define_language! {
     }
 }
 
+/// Words of random simulation per e-class; 64 input patterns per word
+pub const SIG_WORDS: usize = 4;
+pub type Sig = [u64; SIG_WORDS];
+
+/// Random simulation patterns of the input `name`, the same in every run.
+pub fn input_signature(name: &str) -> Sig {
+    // FNV-1a, so the patterns do not depend on the hasher of the standard library
+    let seed = name.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    std::array::from_fn(|_| rng.random())
+}
+
+fn zip_sig(a: Option<Sig>, b: Option<Sig>, f: impl Fn(u64, u64) -> u64) -> Option<Sig> {
+    let (a, b) = (a?, b?);
+    Some(std::array::from_fn(|i| f(a[i], b[i])))
+}
+
+/// What is known about the value of an e-class while saturating.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropData {
+    /// Computable without the key: public inputs, constants, and gates over plaintext operands only
+    pub plain: bool,
+    /// Value of the class, if it is constant
+    pub constant: Option<bool>,
+    /// Fewest levels of any term in the class under the cost model; gates with a plaintext
+    /// operand are free
+    pub min_depth: usize,
+    /// Value of the class under random input patterns; none for output bundles
+    pub sig: Option<Sig>,
+}
+
+impl PropData {
+    /// Data of `enode`, given the data of its children.
+    pub fn of(enode: &Prop, analysis: &PropAnalysis, child: impl Fn(Id) -> PropData) -> Self {
+        let value = |i: &Id| child(*i).constant;
+        let constant = match enode {
+            Prop::Bool(b) => Some(*b),
+            Prop::Int(n) => Some(*n != 0),
+            Prop::Not(a) => value(a).map(|a| !a),
+            Prop::And([a, b]) => match (value(a), value(b)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Prop::Or([a, b]) => match (value(a), value(b)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Prop::Xor([a, b]) => value(a).zip(value(b)).map(|(a, b)| a ^ b),
+            _ => None,
+        };
+        let plain = match enode {
+            Prop::Symbol(s) => analysis.public.contains(s),
+            Prop::Bool(_) | Prop::Int(_) => true,
+            // output bundles are never evaluated in plaintext
+            Prop::Concat(_) | Prop::Concat2(_) | Prop::Connect(_) => false,
+            _ => enode.all(|c| child(c).plain),
+        } || constant.is_some();
+        let operands = enode.fold(0, |d, c| d.max(child(c).min_depth));
+        let min_depth = match enode {
+            _ if constant.is_some() => 0,
+            _ => operands + analysis.levels(enode, |c| child(c).plain),
+        };
+        let sim = |i: &Id| child(*i).sig;
+        let sig = match enode {
+            _ if constant.is_some() => Some([if constant.unwrap() { !0 } else { 0 }; SIG_WORDS]),
+            Prop::Symbol(s) => Some(input_signature(s.as_str())),
+            Prop::Not(a) => sim(a).map(|a| a.map(|w| !w)),
+            Prop::And([a, b]) => zip_sig(sim(a), sim(b), |x, y| x & y),
+            Prop::Or([a, b]) => zip_sig(sim(a), sim(b), |x, y| x | y),
+            Prop::Xor([a, b]) => zip_sig(sim(a), sim(b), |x, y| x ^ y),
+            Prop::Connect(a) => sim(a),
+            _ => None,
+        };
+        PropData { plain, constant, min_depth, sig }
+    }
+}
+
+/// Tracks plaintext classes, folds constants and keeps a lower bound on the depth of every
+/// e-class under `model`. Inputs named in `public` are plaintext; all others are encrypted.
+#[derive(Clone)]
+pub struct PropAnalysis {
+    pub public: HashSet<Symbol>,
+    pub model: Arc<dyn HeCostModel>,
+    /// What is merging classes right now, e.g. "rule and-comm"; none while rebuilding
+    pub cause: Option<String>,
+    /// Set once two classes with different constant values are merged
+    pub contradiction: Option<String>,
+}
+
+impl Default for PropAnalysis {
+    fn default() -> Self {
+        PropAnalysis { public: HashSet::new(), model: Arc::new(LeveledBgv), cause: None, contradiction: None }
+    }
+}
+
+impl fmt::Debug for PropAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PropAnalysis")
+            .field("public", &self.public)
+            .field("model", &self.model.name())
+            .field("contradiction", &self.contradiction)
+            .finish()
+    }
+}
+
+impl PropAnalysis {
+    pub fn new(public: HashSet<Symbol>, model: Arc<dyn HeCostModel>) -> Self {
+        PropAnalysis { public, model, ..PropAnalysis::default() }
+    }
+
+    /// Levels `enode` adds on top of its deepest operand under the cost model, given which
+    /// classes are plaintext.
+    pub fn levels(&self, enode: &Prop, plain: impl Fn(Id) -> bool) -> usize {
+        match enode {
+            Prop::Symbol(s) => self.model.arrival_level(s.as_str()),
+            _ if enode.any(plain) => 0,
+            _ => self.model.gate_depth(&PropId::of(enode)),
+        }
+    }
+}
+
+impl Analysis<Prop> for PropAnalysis {
+    type Data = PropData;
+
+    fn make(egraph: &mut EGraph<Prop, Self>, enode: &Prop) -> Self::Data {
+        PropData::of(enode, &egraph.analysis, |c| egraph[c].data.clone())
+    }
+
+    fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
+        // one plaintext (or constant, or shallow) term is enough for the whole class
+        let plain = merge_max(&mut a.plain, b.plain);
+        let constant = merge_option(&mut a.constant, b.constant, |a, b| {
+            if *a != b && self.contradiction.is_none() {
+                let cause = self.cause.as_deref().unwrap_or("rebuilding the e-graph");
+                self.contradiction = Some(format!("{} proved 0 == 1", cause));
+            }
+            DidMerge(false, false)
+        });
+        let min_depth = merge_min(&mut a.min_depth, b.min_depth);
+        // equal functions simulate the same, so there is nothing to reconcile
+        let sig = merge_option(&mut a.sig, b.sig, |_, _| DidMerge(false, false));
+        plain | constant | min_depth | sig
+    }
+
+    fn modify(egraph: &mut EGraph<Prop, Self>, id: Id) {
+        if let Some(b) = egraph[id].data.constant {
+            let folded = egraph.add(Prop::Bool(b));
+            egraph.union(id, folded);
+            // the gates computing a constant are never worth extracting
+            egraph[id].nodes.retain(|n| n.is_leaf());
+        }
+    }
+}
+
+/// Applies `rhs` only where the result could be as shallow as the matched class already is.
+/// A deeper term can never shorten the critical path, so with depth as the goal it is not added.
+pub struct DepthGuard {
+    pub rhs: Pattern<Prop>,
+}
+
+impl Applier<Prop, PropAnalysis> for DepthGuard {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Prop, PropAnalysis>,
+        eclass: Id,
+        subst: &Subst,
+        searcher_ast: Option<&PatternAst<Prop>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let mut data: Vec<PropData> = Vec::new();
+        for node in self.rhs.ast.as_ref() {
+            let d = match node {
+                ENodeOrVar::Var(v) => egraph[subst[*v]].data.clone(),
+                ENodeOrVar::ENode(n) => PropData::of(n, &egraph.analysis, |c| data[usize::from(c)].clone()),
+            };
+            data.push(d);
+        }
+        if data.last().unwrap().min_depth > egraph[eclass].data.min_depth {
+            return vec![];
+        }
+        self.rhs.apply_one(egraph, eclass, subst, searcher_ast, rule_name)
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.rhs.vars()
+    }
+}
+
 pub enum PropId {
     And,
+    Or,
     Not,
     Xor,
     Lit,
@@ -68,3 +267,34 @@ impl PartialOrd for DepthArea {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost_model::CkksBoolean;
+
+    fn xor_depth(public: &[&str], model: Arc<dyn HeCostModel>) -> usize {
+        let mut egraph: EGraph<Prop, PropAnalysis> = EGraph::new(PropAnalysis::new(public.iter().map(|s| Symbol::new(s)).collect(), model));
+        let a = egraph.add(Prop::Symbol(Symbol::new("a")));
+        let b = egraph.add(Prop::Symbol(Symbol::new("b")));
+        let x = egraph.add(Prop::Xor([a, b]));
+        egraph[x].data.min_depth
+    }
+
+    #[test]
+    fn min_depth_follows_the_cost_model() {
+        assert_eq!(xor_depth(&[], Arc::new(LeveledBgv)), 0);
+        assert_eq!(xor_depth(&[], Arc::new(CkksBoolean)), 1);
+        assert_eq!(xor_depth(&["b"], Arc::new(CkksBoolean)), 0);
+    }
+
+    #[test]
+    fn merging_true_with_false_blames_the_cause() {
+        let mut egraph: EGraph<Prop, PropAnalysis> = EGraph::default();
+        let t = egraph.add(Prop::Bool(true));
+        let f = egraph.add(Prop::Bool(false));
+        egraph.analysis.cause = Some("rule unsound".to_string());
+        egraph.union(t, f);
+        assert_eq!(egraph.analysis.contradiction.as_deref(), Some("rule unsound proved 0 == 1"));
+    }
+}
@@ -0,0 +1,79 @@
+// Timing constraints on the network boundary, for circuits composed with other HE computations.
+//
+// One constraint per line, `#` starts a comment:
+//   ARRIVAL <input> <level>    input is already <level> multiplications deep
+//   REQUIRED <output> <level>  output must be ready by <level>
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, Default)]
+pub struct Constraints {
+    pub arrival: HashMap<String, usize>,
+    pub required: HashMap<String, usize>,
+}
+
+/// A problem with a constraint file, at one of its lines.
+#[derive(Debug)]
+pub struct ConstraintError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "constraint line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Constraints {
+    pub fn parse(contents: &str, innodes: &str, outnodes: &str) -> Result<Self, ConstraintError> {
+        let mut constraints = Constraints::default();
+        for (i, line) in contents.lines().enumerate() {
+            let error = |msg: String| ConstraintError { line: i + 1, msg };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (kind, net, level) = match fields[..] {
+                [kind, net, level] => (kind, net, level),
+                _ => return Err(error(format!("expected \"<ARRIVAL|REQUIRED> <net> <level>\"; got \"{}\"", line))),
+            };
+            let level: usize = level.parse().map_err(|_| error(format!("bad level {}", level)))?;
+            let (map, nets) = match kind {
+                "ARRIVAL" => (&mut constraints.arrival, innodes),
+                "REQUIRED" => (&mut constraints.required, outnodes),
+                _ => return Err(error(format!("unknown kind {}", kind))),
+            };
+            if !nets.split(" ").any(|n| n == net) {
+                return Err(error(format!("{} is not a{} of the network", net, if kind == "ARRIVAL" { "n input" } else { "n output" })));
+            }
+            map.insert(net.to_string(), level);
+        }
+        Ok(constraints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arrival_and_required_levels() {
+        let contents = "# boundary\nARRIVAL a 2\n\nREQUIRED o 5  # due\n";
+        let constraints = Constraints::parse(contents, "a b", "o").unwrap();
+        assert_eq!(constraints.arrival, HashMap::from([("a".to_string(), 2)]));
+        assert_eq!(constraints.required, HashMap::from([("o".to_string(), 5)]));
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let line = |contents: &str| Constraints::parse(contents, "a b", "o").unwrap_err().line;
+        assert_eq!(line("ARRIVAL a 1\nARRIVAL a"), 2);
+        assert_eq!(line("ARRIVAL a x"), 1);
+        assert_eq!(line("\nLATE o 1"), 2);
+        // outputs cannot arrive, inputs cannot be required
+        assert_eq!(line("ARRIVAL o 1"), 1);
+        assert_eq!(line("REQUIRED a 1"), 1);
+    }
+}
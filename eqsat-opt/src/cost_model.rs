@@ -0,0 +1,492 @@
+// Homomorphic-encryption cost models shared by every extractor
+// (egg cost functions, ILP and the serialized-graph extractors).
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use egraph_serialize::{EGraph, Node};
+
+use crate::common::{Prop, PropId};
+use crate::serde::{decode_op_string, is_plain};
+
+/// Prices gates and whole networks for a particular HE scheme.
+///
+/// Scheme models implement `name`, `gate_cost`, `gate_depth` and `depth_penalty`. Wrappers that
+/// adjust another model return it from `base` and only override what they change; every other
+/// method forwards to the base.
+pub trait HeCostModel: Send + Sync {
+    /// The model this one wraps, if any.
+    fn base(&self) -> Option<&dyn HeCostModel> {
+        None
+    }
+
+    fn name(&self) -> &'static str {
+        self.base().expect("a cost model without a base must have a name").name()
+    }
+
+    /// Evaluation cost of a single gate of kind `op`.
+    fn gate_cost(&self, op: &PropId) -> f64 {
+        self.base().map_or(0.0, |b| b.gate_cost(op))
+    }
+
+    /// Number of levels a gate of kind `op` adds to the critical path.
+    fn gate_depth(&self, op: &PropId) -> usize {
+        self.base().map_or(0, |b| b.gate_depth(op))
+    }
+
+    /// Penalty for a network whose critical path is `depth` levels long.
+    fn depth_penalty(&self, depth: u64) -> f64 {
+        self.base().map_or(0.0, |b| b.depth_penalty(depth))
+    }
+
+    /// Combine depth and total gate cost into a single scalar; lower is better.
+    fn combine(&self, depth: u64, cost: f64) -> f64 {
+        match self.base() {
+            Some(b) => b.combine(depth, cost),
+            None => self.depth_penalty(depth) * cost,
+        }
+    }
+
+    /// Penalty for a network that keeps `peak_live` ciphertexts in memory at once.
+    fn memory_penalty(&self, peak_live: u64) -> f64 {
+        self.base().map_or(0.0, |b| b.memory_penalty(peak_live))
+    }
+
+    /// Gates a batched (SIMD) evaluator runs in one operation, if the network is scored per batch.
+    fn simd_slots(&self) -> Option<usize> {
+        self.base().and_then(|b| b.simd_slots())
+    }
+
+    /// Penalty for a network that needs `batches` batched multiplications, level by level.
+    fn batch_penalty(&self, batches: u64) -> f64 {
+        self.base().map_or(0.0, |b| b.batch_penalty(batches))
+    }
+
+    /// Level at which input `input` becomes available.
+    fn arrival_level(&self, input: &str) -> usize {
+        self.base().map_or(0, |b| b.arrival_level(input))
+    }
+
+    /// Level by which output `output` must be computed, if it has a deadline of its own.
+    fn required_level(&self, output: &str) -> Option<usize> {
+        self.base().and_then(|b| b.required_level(output))
+    }
+
+    /// Whether partial solutions are ranked by depth first and cost second, which suits
+    /// leveled schemes, rather than by `combine`.
+    fn depth_major(&self) -> bool {
+        self.base().is_none_or(|b| b.depth_major())
+    }
+
+    /// Whether written networks keep XOR as a single gate instead of expanding it into AND/OR.
+    fn native_xor(&self) -> bool {
+        self.base().is_some_and(|b| b.native_xor())
+    }
+
+    fn node_cost(&self, egraph: &EGraph, node: &Node) -> f64 {
+        if let Some(b) = self.base() {
+            return b.node_cost(egraph, node);
+        }
+        if is_plain(egraph, node) {
+            return 0.0;
+        }
+        self.gate_cost(&decode_op_string(&node.op))
+    }
+
+    fn node_depth(&self, egraph: &EGraph, node: &Node) -> usize {
+        if let Some(b) = self.base() {
+            return b.node_depth(egraph, node);
+        }
+        if is_plain(egraph, node) {
+            return 0;
+        }
+        self.gate_depth(&decode_op_string(&node.op))
+    }
+}
+
+/// Leveled BGV/BFV: only ciphertext multiplications (AND, and OR = a + b - ab) cost anything,
+/// and the cost of each one grows with the number of levels the parameters must support.
+#[derive(Clone, Debug, Default)]
+pub struct LeveledBgv;
+
+impl HeCostModel for LeveledBgv {
+    fn name(&self) -> &'static str {
+        "bgv"
+    }
+
+    fn gate_cost(&self, op: &PropId) -> f64 {
+        match op {
+            PropId::And | PropId::Or => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    fn gate_depth(&self, op: &PropId) -> usize {
+        match op {
+            PropId::And | PropId::Or => 1,
+            _ => 0,
+        }
+    }
+
+    fn depth_penalty(&self, depth: u64) -> f64 {
+        (depth * depth) as f64
+    }
+}
+
+/// CKKS-style boolean emulation: XOR is evaluated as a + b - 2ab, so it needs a multiplication
+/// (and a rescale) just like AND. Each extra level makes every multiplication more expensive.
+#[derive(Clone, Debug, Default)]
+pub struct CkksBoolean;
+
+impl HeCostModel for CkksBoolean {
+    fn name(&self) -> &'static str {
+        "ckks"
+    }
+
+    fn gate_cost(&self, op: &PropId) -> f64 {
+        match op {
+            PropId::And | PropId::Or | PropId::Xor => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    fn gate_depth(&self, op: &PropId) -> usize {
+        match op {
+            PropId::And | PropId::Or | PropId::Xor => 1,
+            _ => 0,
+        }
+    }
+
+    fn depth_penalty(&self, depth: u64) -> f64 {
+        depth as f64
+    }
+}
+
+/// TFHE/CGGI gate bootstrapping: every binary gate is bootstrapped and NOT is free.
+/// Depth only matters as latency, weighted by `latency_weight`.
+#[derive(Clone, Debug, Default)]
+pub struct TfheGate {
+    pub latency_weight: f64,
+}
+
+impl HeCostModel for TfheGate {
+    fn name(&self) -> &'static str {
+        "tfhe"
+    }
+
+    fn gate_cost(&self, op: &PropId) -> f64 {
+        match op {
+            PropId::And | PropId::Or | PropId::Xor => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    fn gate_depth(&self, op: &PropId) -> usize {
+        match op {
+            PropId::And | PropId::Or | PropId::Xor => 1,
+            _ => 0,
+        }
+    }
+
+    fn depth_penalty(&self, depth: u64) -> f64 {
+        self.latency_weight * depth as f64
+    }
+
+    fn combine(&self, depth: u64, cost: f64) -> f64 {
+        cost + self.depth_penalty(depth)
+    }
+
+    fn depth_major(&self) -> bool {
+        false
+    }
+
+    fn native_xor(&self) -> bool {
+        true
+    }
+}
+
+/// Ranks partial solutions by gate cost plus `weight` per level, on top of another model's gate
+/// costs and depths. Sweeping `weight` walks extractors along the depth/count trade-off.
+pub struct DepthWeighted<'a> {
+    pub base: &'a dyn HeCostModel,
+    pub weight: f64,
+}
+
+impl<'a> HeCostModel for DepthWeighted<'a> {
+    fn base(&self) -> Option<&dyn HeCostModel> {
+        Some(self.base)
+    }
+
+    fn depth_penalty(&self, depth: u64) -> f64 {
+        self.weight * depth as f64
+    }
+
+    fn combine(&self, depth: u64, cost: f64) -> f64 {
+        cost + self.depth_penalty(depth)
+    }
+
+    fn depth_major(&self) -> bool {
+        false
+    }
+}
+
+/// Adds `weight` per XOR gate to another model's gate costs, so that extractors also trade the
+/// XOR count against ANDs and depth (XORs are free under the leveled models).
+pub struct XorWeighted<'a> {
+    pub base: &'a dyn HeCostModel,
+    pub weight: f64,
+}
+
+impl<'a> HeCostModel for XorWeighted<'a> {
+    fn base(&self) -> Option<&dyn HeCostModel> {
+        Some(self.base)
+    }
+
+    fn gate_cost(&self, op: &PropId) -> f64 {
+        self.base.gate_cost(op) + if matches!(op, PropId::Xor) { self.weight } else { 0.0 }
+    }
+
+    fn node_cost(&self, egraph: &EGraph, node: &Node) -> f64 {
+        let xor = matches!(decode_op_string(&node.op), PropId::Xor) && !is_plain(egraph, node);
+        self.base.node_cost(egraph, node) + if xor { self.weight } else { 0.0 }
+    }
+}
+
+/// Adds `weight` per ciphertext live at the peak to another model's network cost.
+/// The peak is only known once a network is written, so no extractor sees it during its search:
+/// the penalty ranks finished candidates (incumbents, portfolio and Pareto entries) and mostly
+/// breaks ties between them.
+pub struct MemoryWeighted {
+    pub base: Box<dyn HeCostModel>,
+    pub weight: f64,
+}
+
+impl HeCostModel for MemoryWeighted {
+    fn base(&self) -> Option<&dyn HeCostModel> {
+        Some(self.base.as_ref())
+    }
+
+    fn memory_penalty(&self, peak_live: u64) -> f64 {
+        self.weight * peak_live as f64
+    }
+}
+
+/// Adds `weight` per batched multiplication to another model's network cost, for SIMD evaluators
+/// that run all gates of a level together, `slots` at a time. This favors networks with
+/// few and narrow levels.
+pub struct SimdBatched {
+    pub base: Box<dyn HeCostModel>,
+    pub slots: usize,
+    pub weight: f64,
+}
+
+impl HeCostModel for SimdBatched {
+    fn base(&self) -> Option<&dyn HeCostModel> {
+        Some(self.base.as_ref())
+    }
+
+    fn simd_slots(&self) -> Option<usize> {
+        Some(self.slots)
+    }
+
+    fn batch_penalty(&self, batches: u64) -> f64 {
+        self.weight * batches as f64
+    }
+}
+
+/// Another model with timing constraints: inputs in `arrival` start at that level
+/// instead of 0, and outputs in `required` must be ready by that level.
+pub struct Timed {
+    pub base: Box<dyn HeCostModel>,
+    pub arrival: HashMap<String, usize>,
+    pub required: HashMap<String, usize>,
+}
+
+impl HeCostModel for Timed {
+    fn base(&self) -> Option<&dyn HeCostModel> {
+        Some(self.base.as_ref())
+    }
+
+    fn arrival_level(&self, input: &str) -> usize {
+        self.arrival.get(input).copied().unwrap_or(0)
+    }
+
+    fn required_level(&self, output: &str) -> Option<usize> {
+        self.required.get(output).copied()
+    }
+
+    fn node_depth(&self, egraph: &EGraph, node: &Node) -> usize {
+        match decode_op_string(&node.op) {
+            PropId::Sym => self.arrival_level(&node.op),
+            _ => self.base.node_depth(egraph, node),
+        }
+    }
+}
+
+/// Built-in cost model profiles selectable from the CLI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CostProfile {
+    /// Leveled BGV/BFV (only ANDs are costly)
+    Bgv,
+    /// CKKS-style boolean emulation (AND and XOR are multiplications)
+    Ckks,
+    /// TFHE gate bootstrapping (every binary gate is bootstrapped)
+    Tfhe,
+}
+
+impl CostProfile {
+    pub fn model(self, latency_weight: f64) -> Box<dyn HeCostModel> {
+        match self {
+            CostProfile::Bgv => Box::new(LeveledBgv),
+            CostProfile::Ckks => Box::new(CkksBoolean),
+            CostProfile::Tfhe => Box::new(TfheGate { latency_weight }),
+        }
+    }
+}
+
+impl PropId {
+    pub fn of(enode: &Prop) -> Self {
+        match enode {
+            Prop::And(_) => PropId::And,
+            Prop::Or(_) => PropId::Or,
+            Prop::Xor(_) => PropId::Xor,
+            Prop::Not(_) => PropId::Not,
+            Prop::Bool(_) | Prop::Int(_) => PropId::Lit,
+            Prop::Symbol(_) => PropId::Sym,
+            Prop::Connect(_) | Prop::Concat(_) | Prop::Concat2(_) => PropId::Ct,
+        }
+    }
+}
+
+/// Summary of an extracted network under a cost model.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetworkCost {
+    /// Critical path length, in the model's levels
+    pub md: u64,
+    /// Number of AND gates
+    pub mc: u64,
+    /// Number of XOR gates
+    pub xc: u64,
+    /// Sum of the model's gate costs
+    pub cost: f64,
+    /// Most ciphertexts live at once under the written equation order
+    pub peak_live: u64,
+    /// Batched multiplications for a level-by-level SIMD evaluation
+    pub batches: u64,
+    /// Levels between the tightest output and its required level; negative if it is late
+    pub slack: i64,
+}
+
+impl NetworkCost {
+    pub fn add_node(&mut self, model: &dyn HeCostModel, egraph: &EGraph, node: &Node) {
+        // gates with a plaintext operand are not counted
+        let plain = is_plain(egraph, node);
+        match decode_op_string(&node.op) {
+            PropId::And if !plain => self.mc += 1,
+            PropId::Xor if !plain => self.xc += 1,
+            _ => {}
+        }
+        self.cost += model.node_cost(egraph, node);
+    }
+
+    pub fn scalar(&self, model: &dyn HeCostModel) -> f64 {
+        model.combine(self.md, self.cost) + model.memory_penalty(self.peak_live) + model.batch_penalty(self.batches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bgv_only_multiplications_cost() {
+        let model = CostProfile::Bgv.model(0.0);
+        assert_eq!(model.gate_cost(&PropId::And), 1.0);
+        assert_eq!(model.gate_cost(&PropId::Or), 1.0);
+        assert_eq!(model.gate_cost(&PropId::Xor), 0.0);
+        assert_eq!(model.gate_depth(&PropId::Xor), 0);
+        assert_eq!(model.gate_depth(&PropId::Not), 0);
+        // cost grows with the square of the depth
+        assert_eq!(model.combine(3, 2.0), 18.0);
+        assert!(model.depth_major());
+        assert!(!model.native_xor());
+    }
+
+    #[test]
+    fn ckks_xor_is_a_multiplication() {
+        let model = CostProfile::Ckks.model(0.0);
+        assert_eq!(model.gate_cost(&PropId::Xor), 1.0);
+        assert_eq!(model.gate_depth(&PropId::Xor), 1);
+        assert_eq!(model.gate_cost(&PropId::Not), 0.0);
+        assert_eq!(model.combine(3, 2.0), 6.0);
+    }
+
+    #[test]
+    fn tfhe_adds_latency() {
+        let model = CostProfile::Tfhe.model(0.5);
+        assert_eq!(model.gate_cost(&PropId::Xor), 1.0);
+        assert_eq!(model.gate_cost(&PropId::Not), 0.0);
+        assert_eq!(model.combine(4, 10.0), 12.0);
+        assert!(!model.depth_major());
+        assert!(model.native_xor());
+    }
+
+    #[test]
+    fn xor_weight_prices_xors_on_top_of_the_base() {
+        let base = LeveledBgv;
+        let model = XorWeighted { base: &base, weight: 0.5 };
+        assert_eq!(model.gate_cost(&PropId::Xor), 0.5);
+        assert_eq!(model.gate_cost(&PropId::And), 1.0);
+        assert_eq!(model.gate_depth(&PropId::Xor), 0);
+        let node = |op: &str| Node { op: op.to_string(), children: vec![], eclass: egraph_serialize::ClassId::new(0), cost: egraph_serialize::Cost::new(0.0).unwrap(), subsumed: false };
+        assert_eq!(model.node_cost(&EGraph::default(), &node("^")), 0.5);
+        assert_eq!(model.node_cost(&EGraph::default(), &node("*")), 1.0);
+        assert!(model.depth_major());
+    }
+
+    #[test]
+    fn network_cost_counts_gates() {
+        let model = LeveledBgv;
+        let mut cost = NetworkCost::default();
+        for op in ["*", "*", "^", "!"] {
+            let node = Node {
+                op: op.to_string(),
+                children: vec![],
+                eclass: egraph_serialize::ClassId::new(0),
+                cost: egraph_serialize::Cost::new(model.gate_cost(&decode_op_string(op))).unwrap(),
+                subsumed: false,
+            };
+            cost.add_node(&model, &EGraph::default(), &node);
+        }
+        assert_eq!((cost.mc, cost.xc, cost.cost), (2, 1, 2.0));
+        cost.md = 2;
+        assert_eq!(cost.scalar(&model), 8.0);
+    }
+
+    #[test]
+    fn gates_with_a_plaintext_operand_are_free() {
+        use egraph_serialize::{ClassData, ClassId, Cost, NodeId};
+        let model = CostProfile::Ckks.model(0.0);
+        let mut egraph = EGraph::default();
+        for (class, op) in [(0, "k"), (1, "x")] {
+            let node = Node { op: op.to_string(), children: vec![], eclass: ClassId::new(class), cost: Cost::new(0.0).unwrap(), subsumed: false };
+            egraph.add_node(NodeId::new(0, class), node);
+        }
+        egraph.class_data.insert(ClassId::new(0), ClassData { typ: Some(crate::serde::PLAIN_TYPE.to_string()) });
+        let gate = |children: Vec<NodeId>| Node { op: "*".to_string(), children, eclass: ClassId::new(2), cost: Cost::new(1.0).unwrap(), subsumed: false };
+
+        let (plain, cipher) = (gate(vec![NodeId::new(0, 0), NodeId::new(0, 1)]), gate(vec![NodeId::new(0, 1), NodeId::new(0, 1)]));
+        assert_eq!((model.node_cost(&egraph, &plain), model.node_depth(&egraph, &plain)), (0.0, 0));
+        assert_eq!((model.node_cost(&egraph, &cipher), model.node_depth(&egraph, &cipher)), (1.0, 1));
+        let mut cost = NetworkCost::default();
+        cost.add_node(model.as_ref(), &egraph, &plain);
+        assert_eq!((cost.mc, cost.cost), (0, 0.0));
+    }
+
+    #[test]
+    fn depth_weighted_ranks_by_cost_plus_depth() {
+        let model = DepthWeighted { base: &CkksBoolean, weight: 2.0 };
+        assert_eq!(model.gate_cost(&PropId::Xor), 1.0);
+        assert_eq!(model.combine(3, 1.0), 7.0);
+        assert!(!model.depth_major());
+    }
+}
@@ -0,0 +1,151 @@
+// Explanations of why each output of the extracted network equals the same output of the input
+// network, from egg's proof-producing e-graph. Unions made while reading a trace are justified
+// by the trace instruction that made them.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use egg::*;
+use indexmap::IndexMap;
+
+use crate::common::{Prop, PropAnalysis};
+use crate::extraction_ser::parse_equation;
+
+/// Term computing `class` in the network, as e-nodes with classes as children.
+fn term(nodes: &HashMap<Id, Prop>, class: Id, expr: &mut RecExpr<Prop>, memo: &mut HashMap<Id, Id>) -> Option<Id> {
+    if let Some(id) = memo.get(&class) {
+        return Some(*id);
+    }
+    let mut node = nodes.get(&class)?.clone();
+    for child in node.children_mut() {
+        *child = term(nodes, *child, expr, memo)?;
+    }
+    let id = expr.add(node);
+    memo.insert(class, id);
+    Some(id)
+}
+
+/// The expression of every output of `network`, which must have been extracted from `egraph`.
+fn network_exprs(egraph: &EGraph<Prop, PropAnalysis>, network: &str, innodes: &str, outnodes: &str) -> IndexMap<String, RecExpr<Prop>> {
+    let inputs: HashSet<&str> = innodes.split(" ").collect();
+    let outputs: HashSet<&str> = outnodes.split(" ").collect();
+    let mut nodes: HashMap<Id, Prop> = HashMap::new();
+    let mut roots: IndexMap<String, Id> = IndexMap::new();
+    for line in network.lines().filter(|l| !l.starts_with('#')) {
+        let Some((lhs, rhs)) = line.split_once(" = ") else { continue };
+        if outputs.contains(lhs) {
+            if let Some(class) = rhs.trim_end_matches(';').strip_prefix('n').and_then(|c| c.parse::<usize>().ok()) {
+                roots.insert(lhs.to_string(), Id::from(class));
+            }
+            continue;
+        }
+        let Some((class, candidates)) = parse_equation(line, &inputs) else { continue };
+        // constants may be either literal; take the one in the e-graph
+        let node = candidates
+            .iter()
+            .find(|n| egraph.lookup((*n).clone().map_children(|c| egraph.find(c))).is_some())
+            .unwrap_or(&candidates[0]);
+        nodes.insert(class, node.clone());
+    }
+    roots
+        .into_iter()
+        .filter_map(|(name, class)| {
+            let mut expr = RecExpr::default();
+            term(&nodes, class, &mut expr, &mut HashMap::new())?;
+            Some((name, expr))
+        })
+        .collect()
+}
+
+/// Writes `<stem>.explain`: for each output, the rewrites taking its expression in the input
+/// network (`originals`, uncanonical ids) to its expression in `network`.
+pub fn write_explanations(
+    egraph: &mut EGraph<Prop, PropAnalysis>,
+    originals: &IndexMap<String, Id>,
+    network: &str,
+    innodes: &str,
+    outnodes: &str,
+    outfile: &Path,
+) {
+    let extracted = network_exprs(egraph, network, innodes, outnodes);
+    let mut text = String::new();
+    for (name, original) in originals {
+        let Some(expr) = extracted.get(name) else {
+            text.push_str(&format!("# {}: not in the extracted network\n\n", name));
+            continue;
+        };
+        let original = egraph.id_to_expr(*original);
+        let mut explanation = egraph.explain_equivalence(&original, expr);
+        let rewrites = explanation.make_flat_explanation().len().saturating_sub(1);
+        println!("explanation of {}: {} rewrites", name, rewrites);
+        text.push_str(&format!("# {} ({} rewrites)\n{}\n\n", name, rewrites, explanation.get_flat_string()));
+    }
+    let stem = outfile.file_stem().unwrap().to_string_lossy();
+    std::fs::write(outfile.with_file_name(format!("{}.explain", stem)), text).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost_model::LeveledBgv;
+    use crate::extraction_ser::dag_network_writer;
+    use crate::{global_greedy_dag, serde};
+
+    /// An e-graph with explanations holding the output `o = original`.
+    fn egraph(original: &str) -> (EGraph<Prop, PropAnalysis>, Id) {
+        let mut egraph = EGraph::new(PropAnalysis::default()).with_explanations_enabled();
+        let id = egraph.add_expr_uncanonical(&original.parse().unwrap());
+        (egraph, id)
+    }
+
+    /// The greedy extraction of `o` and its explanation file.
+    fn explain(egraph: &mut EGraph<Prop, PropAnalysis>, original: Id, test: &str) -> (String, String) {
+        egraph.rebuild();
+        let root = egraph.find(original);
+        let ser = serde::serialize_in_mem(egraph, [&root], &LeveledBgv);
+        let mut result = global_greedy_dag::mc_extract(&ser, &ser.root_eclasses, std::collections::HashMap::new(), &LeveledBgv);
+        let (_, network) = dag_network_writer(&ser, &mut result, &IndexMap::from([("o".to_string(), root)]), &LeveledBgv);
+        let extracted = network_exprs(egraph, &network, "a b", "o")["o"].to_string();
+
+        let outfile = std::env::temp_dir().join(format!("eqsat-opt-explain-{}-{}.eqn", std::process::id(), test));
+        write_explanations(egraph, &IndexMap::from([("o".to_string(), original)]), &network, "a b", "o", &outfile);
+        let explain_file = outfile.with_extension("explain");
+        let text = std::fs::read_to_string(&explain_file).unwrap();
+        std::fs::remove_file(&explain_file).unwrap();
+        (extracted, text)
+    }
+
+    /// The flat explanation from `original` to `extracted`, one term per step.
+    fn steps(egraph: &mut EGraph<Prop, PropAnalysis>, original: Id, extracted: &str) -> Vec<FlatTerm<Prop>> {
+        let original = egraph.id_to_expr(original);
+        egraph.explain_equivalence(&original, &extracted.parse().unwrap()).make_flat_explanation().clone()
+    }
+
+    #[test]
+    fn rewrites_are_explained_by_their_rule() {
+        let (mut egraph, original) = egraph("(* a (* a b))");
+        let rules: Vec<Rewrite<Prop, PropAnalysis>> = vec![rewrite!("absorb"; "(* ?x (* ?x ?y))" => "(* ?x ?y)")];
+        let mut egraph = Runner::default().with_egraph(std::mem::take(&mut egraph)).with_iter_limit(2).run(&rules).egraph;
+        let (extracted, text) = explain(&mut egraph, original, "rewrite");
+        assert_eq!(extracted, "(* a b)");
+        let steps = steps(&mut egraph, original, &extracted);
+        assert_eq!(steps.first().unwrap().get_recexpr().to_string(), "(* a (* a b))");
+        assert_eq!(steps.last().unwrap().get_recexpr().to_string(), "(* a b)");
+        assert_eq!(text.lines().next(), Some("# o (1 rewrites)"));
+        assert_eq!(text.lines().nth(1), Some("(* a (* a b))"));
+        assert!(text.lines().nth(2).unwrap().contains("Rewrite=> absorb"), "{}", text);
+    }
+
+    #[test]
+    fn trace_unions_are_explained_by_their_instruction() {
+        let (mut egraph, original) = egraph("(* a (* a b))");
+        let new = egraph.add_expr_uncanonical(&"(* a b)".parse().unwrap());
+        egraph.union_trusted(original, new, "trace: U 4 0 3");
+        let (extracted, text) = explain(&mut egraph, original, "trace");
+        assert_eq!(extracted, "(* a b)");
+        let steps = steps(&mut egraph, original, &extracted);
+        assert_eq!(steps.first().unwrap().get_recexpr().to_string(), "(* a (* a b))");
+        assert_eq!(steps.last().unwrap().get_recexpr().to_string(), "(* a b)");
+        assert_eq!(text.lines().nth(1), Some("(* a (* a b))"));
+        assert!(text.lines().nth(2).unwrap().contains("trace: U 4 0 3"), "{}", text);
+    }
+}
@@ -8,19 +8,64 @@ use std::f64::INFINITY;
 use std::path::Path;
 use std::usize::MAX;
 
-use crate::common::{DepthArea, PropId};
+use crate::common::{DepthArea, Prop, PropId};
+use crate::cost_model::{HeCostModel, NetworkCost};
 use crate::md_slack::MdBounds;
 
 use indexmap::IndexMap;
 
 pub type ExtractionResult = IndexMap<ClassId,(usize,NodeId)>;
 
-pub struct MixedCost {
+/// Walks the chosen node of each class from the roots, returning the MD, the total cost and
+/// the extraction with its levels, in topological order. `None` if a class is
+/// missing a choice or the choices form a cycle.
+pub fn levelize(
+    egraph: &EGraph,
+    roots: &[ClassId],
+    choices: &HashMap<ClassId, NodeId>,
+    model: &dyn HeCostModel,
+) -> Option<(usize, f64, ExtractionResult)> {
+    let mut result: ExtractionResult = IndexMap::new();
+    let mut visiting: HashSet<ClassId> = HashSet::new();
+    let mut stack: Vec<(ClassId, bool)> = roots.iter().map(|r| (*r, false)).collect();
+    let mut cost = 0.0;
+    while let Some((cid, expanded)) = stack.pop() {
+        if result.contains_key(&cid) {
+            continue;
+        }
+        let node_id = choices.get(&cid)?;
+        let node = &egraph[node_id];
+        if expanded {
+            let level = node
+                .children
+                .iter()
+                .map(|c| result[egraph.nid_to_cid(c)].0)
+                .max()
+                .unwrap_or(0)
+                + model.node_depth(egraph, node);
+            cost += model.node_cost(egraph, node);
+            result.insert(cid, (level, *node_id));
+        } else {
+            if !visiting.insert(cid) {
+                return None;
+            }
+            stack.push((cid, true));
+            for c in &node.children {
+                stack.push((*egraph.nid_to_cid(c), false));
+            }
+        }
+    }
+    let md = roots.iter().map(|r| result[r].0).max().unwrap_or(0);
+    Some((md, cost, result))
+}
+
+pub struct MixedCost<'a> {
     pub enode_opt_lookup: HashMap<NodeId, f64>,
     pub results: ExtractionResult,
-    pub visited: HashSet<ClassId>
+    pub visited: HashSet<ClassId>,
+    pub model: &'a dyn HeCostModel,
 }
-impl MixedCost {
+impl<'a> MixedCost<'a> {
     pub fn select_best_eclass_mixed(&mut self, egraph: &EGraph, eclass: ClassId, depth: usize) -> usize {
         let mut best_cost: DepthArea = DepthArea::max();
         let eclass_s = &egraph[&eclass];
@@ -49,10 +94,7 @@ impl MixedCost {
             let dag_area = *self.enode_opt_lookup
                 .get(&nodeid)
                 .unwrap_or(&INFINITY);
-            let md_cost = match node.op.as_str() {
-                "*" => 1,
-                _ => 0
-            };
+            let md_cost = self.model.node_depth(egraph, node);
             let node_cost = DepthArea {
                 area: dag_area,
                 depth: worst_depth.saturating_add(md_cost)
@@ -68,11 +110,68 @@ impl MixedCost {
     }
 }
 
+/// Writes an extraction as eqn text, leaving it in the order its equations were written.
+pub type NetworkWriter = fn(&EGraph, &mut ExtractionResult, &IndexMap<String, egg::Id>, &dyn HeCostModel) -> (NetworkCost, String);
+
+/// Right-hand side of the equation computing `enode`, e.g. `n3 * n7;`.
+pub fn node_rhs(enode: &Node, model: &dyn HeCostModel) -> String {
+    let child = |i: usize| enode.children[i].class();
+    match crate::serde::decode_op_string(&enode.op) {
+        PropId::And => format!("n{} * n{};", child(0), child(1)),
+        PropId::Or => format!("n{} + n{};", child(0), child(1)),
+        PropId::Xor => {
+            let (a, b) = (child(0), child(1));
+            if model.native_xor() {
+                format!("n{} ^ n{};", a, b)
+            } else {
+                format!("(!n{} * n{}) + (n{} * !n{});", a, b, a, b)
+            }
+        }
+        PropId::Not => format!("!n{};", child(0)),
+        PropId::Ct => format!("n{};", child(0)),
+        PropId::Sym => format!("{};", enode.op),
+        PropId::Lit => (if &enode.op == "true" { "1;" } else { "0;" }).to_string(),
+    }
+}
+
+/// Inverse of `node_rhs`: the class an equation `n<class> = <rhs>` defines and the e-nodes
+/// it may stand for (a constant is either a `Bool` or an `Int`), with classes as children.
+pub fn parse_equation(line: &str, inputs: &HashSet<&str>) -> Option<(egg::Id, Vec<Prop>)> {
+    let (lhs, rhs) = line.split_once(" = ")?;
+    let class = egg::Id::from(lhs.strip_prefix('n')?.parse::<usize>().ok()?);
+    let rhs = rhs.trim_end_matches(';');
+    if inputs.contains(rhs) {
+        return Some((class, vec![Prop::Symbol(rhs.into())]));
+    }
+    if rhs == "0" || rhs == "1" {
+        return Some((class, vec![Prop::Bool(rhs == "1"), Prop::Int((rhs == "1") as u32)]));
+    }
+    // operands in order of first use; an expanded XOR names each of its two twice
+    let mut operands: Vec<egg::Id> = Vec::new();
+    for token in rhs.split(|c: char| !c.is_alphanumeric()) {
+        if let Some(c) = token.strip_prefix('n').and_then(|c| c.parse::<usize>().ok()) {
+            if !operands.contains(&egg::Id::from(c)) {
+                operands.push(egg::Id::from(c));
+            }
+        }
+    }
+    let node = match operands[..] {
+        [a, b] if rhs.contains('^') || rhs.starts_with("(!") => Prop::Xor([a, b]),
+        [a, b] if rhs.contains('*') => Prop::And([a, b]),
+        [a, b] if rhs.contains('+') => Prop::Or([a, b]),
+        [a] if rhs.starts_with('!') => Prop::Not(a),
+        [a] => Prop::Connect(a),
+        _ => return None,
+    };
+    Some((class, vec![node]))
+}
+
 pub fn dag_network_writer(
     egraph: &EGraph,
     cost_analysis: &mut ExtractionResult,
-    out_net_to_eclass: &IndexMap<String, egg::Id>
-) -> (u64, u64, String) {
+    out_net_to_eclass: &IndexMap<String, egg::Id>,
+    model: &dyn HeCostModel
+) -> (NetworkCost, String) {
     // temporary network to hold nodes whose children have not been visited yet
     let mut network: Vec<String> = Vec::new();
     let mut network_classes: Vec<ClassId> = Vec::new();
@@ -102,16 +201,24 @@ pub fn dag_network_writer(
             ckt_md = md;
         }
     }
-    // add the bases to the critical path
-    for o_id in out_net_to_eclass.values() {
+    // outputs without a required level of their own are due with the deepest one
+    let slack = |o_name: &str, o_id: &ClassId| {
+        model.required_level(o_name).unwrap_or(ckt_md) as i64 - cost_analysis.get(o_id).unwrap().0 as i64
+    };
+    let min_slack = out_net_to_eclass
+        .iter()
+        .map(|(o_name, o_id)| slack(o_name, &ClassId::new(Into::<u32>::into(*o_id))))
+        .min()
+        .unwrap_or(0);
+    // add the bases to the critical path: the outputs with the least slack
+    for (o_name, o_id) in out_net_to_eclass.iter() {
         let o_id = ClassId::new(Into::<u32>::into(*o_id));
-        let md = cost_analysis.get(&o_id).unwrap().0;
-        if md == ckt_md {
-            critical_path.insert(o_id, md);
+        if slack(o_name, &o_id) == min_slack {
+            critical_path.insert(o_id, cost_analysis.get(&o_id).unwrap().0);
         }
     }
 
-    let mut ckt_mc: u64 = 0;
+    let mut ckt_cost = NetworkCost { slack: min_slack, ..Default::default() };
     while !todo_nodes.is_empty() {
         let eclass = todo_nodes.pop().unwrap();
         let md = critical_path.get(&eclass).cloned();
@@ -120,53 +227,26 @@ pub fn dag_network_writer(
         // number of children this node introduces
         // may not be fixed if the eclasses have already been visited
         let mut new_children = 0;
-        let mut is_and = false;
+        let mut node_depth = 0;
         let already_seen = eclass_seen.get(&eclass).is_some();
         let already_complete = already_seen && md.is_some();
         if !already_complete {
             let enodeid = &cost_analysis.get(&eclass).unwrap().1;
             let enode = &egraph[enodeid];
             eclass_seen.insert(eclass);
-            let mut children: Option<&[NodeId]> = Some(&enode.children);
-            match crate::serde::decode_op_string(&enode.op) {
-                PropId::And => {
-                    ckt_mc += 1;
-                    let a = enode.children[0].class();
-                    let b = enode.children[1].class();
-                    netd.push_str(format!("n{} * n{};", a, b).as_str());
-                    is_and = true;
-                }
-                PropId::Xor => {
-                    let a = enode.children[0].class();
-                    let b = enode.children[1].class();
-                    //netd.push_str(format!("n{} ^ n{};", a, b).as_str());
-                    netd.push_str(format!("(!n{} * n{}) + (n{} * !n{});", a, b, a, b).as_str());
-                }
-                PropId::Not => {
-                    let a = enode.children[0].class();
-                    netd.push_str(format!("!n{};", a).as_str());
-                }
-                PropId::Ct => {
-                    let a = enode.children[0].class();
-                    netd.push_str(format!("n{};", a).as_str());
-                }
-                PropId::Sym => {
-                    children = None;
-                    netd.push_str(&enode.op);
-                    netd.push(';');
-                }
-                PropId::Lit => {
-                    children = None;
-                    netd.push_str(if &enode.op == "true" {"1;"} else {"0;"} );
-                }
+            let children: Option<&[NodeId]> = Some(&enode.children);
+            if !already_seen {
+                ckt_cost.add_node(model, egraph, enode);
             }
+            node_depth = model.node_depth(egraph, enode);
+            netd.push_str(&node_rhs(enode, model));
             if let Some(children) = children {
                 if let Some(md) = md { 
                     for child_node in children {
                         // on the critical path
                         let child_node = ClassId::new(child_node.class());
                         let child_md = cost_analysis.get(&child_node).unwrap().0;
-                        let is_critical = (is_and && child_md == md - 1) || (!is_and && child_md == md);
+                        let is_critical = child_md + node_depth == md;
                         if is_critical || (eclass_seen.get(&child_node).is_none() && !already_seen) {
                             todo_nodes.push(child_node);
                             if is_critical { critical_path.insert(child_node, child_md);} 
@@ -226,7 +306,10 @@ pub fn dag_network_writer(
 
     *cost_analysis =topo_cost_analysis;// cost_analysis.clone().into_iter().filter(|(k,_)| eclass_seen.contains(k)).collect();
     //(critical_path, 
-    (ckt_md as u64, ckt_mc, real_network.join("\n"))
+    ckt_cost.md = ckt_md as u64;
+    ckt_cost.peak_live = crate::liveness::peak_live(egraph, cost_analysis, out_net_to_eclass);
+    ckt_cost.batches = crate::schedule::batches(egraph, cost_analysis, model);
+    (ckt_cost, real_network.join("\n"))
 }
 
 pub fn ser_egraph_from_file(infile: &str) -> (EGraph,Vec<ClassId>) {
@@ -304,4 +387,44 @@ pub fn ser_egraph_to_dot<T: std::fmt::Display>(egraph: &EGraph, annot: &HashMap<
     dot.push_str(&connections);
     dot.push('}');
     std::fs::write(outfile, dot).unwrap();
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::PropAnalysis;
+    use crate::cost_model::TfheGate;
+    use crate::rules::{load_rules, RuleOptions};
+    use crate::{global_greedy_dag, serde};
+    use egg::{RecExpr, Runner};
+    use std::sync::Arc;
+
+    /// Cost and network of the greedy extraction of `root` under `model`.
+    fn extract(egraph: &egg::EGraph<Prop, PropAnalysis>, root: egg::Id, model: &dyn HeCostModel) -> (NetworkCost, String) {
+        let ser = serde::serialize_in_mem(egraph, [&root], model);
+        let out_net_to_eclass: IndexMap<String, egg::Id> = IndexMap::from([("o".to_string(), root)]);
+        let mut result = global_greedy_dag::mc_extract(&ser, &ser.root_eclasses, HashMap::new(), model);
+        dag_network_writer(&ser, &mut result, &out_net_to_eclass, model)
+    }
+
+    #[test]
+    fn tfhe_rules_lower_the_bootstrapped_gates() {
+        let model = TfheGate { latency_weight: 0.0 };
+        let mut rules = Vec::new();
+        load_rules(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../rules/tfhe.rules")), &RuleOptions::default(), &mut rules).unwrap();
+        let expr: RecExpr<Prop> = "(^ (* a c) (* a d))".parse().unwrap();
+        let mut egraph = egg::EGraph::new(PropAnalysis::new(HashSet::new(), Arc::new(model.clone())));
+        let root = egraph.add_expr(&expr);
+        egraph.rebuild();
+        let (before, _) = extract(&egraph, root, &model);
+
+        let rewrites: Vec<_> = rules.into_iter().map(|r| r.rewrite).collect();
+        let runner = Runner::default().with_egraph(egraph).with_iter_limit(5).run(&rewrites);
+        let root = runner.egraph.find(root);
+        let (after, network) = extract(&runner.egraph, root, &model);
+        // a * (c ^ d): one AND and one XOR, both bootstrapped
+        assert_eq!((before.cost, after.cost), (3.0, 2.0));
+        assert_eq!((after.mc, after.xc), (1, 1));
+        assert!(network.lines().any(|l| l.contains(" ^ ")), "{}", network);
+        assert!(!network.contains("(!"), "{}", network);
+    }
+}
@@ -1,7 +1,6 @@
 // Extraction algorithms working (primarily) on the
 // unserialized E-Graph (from egg itself)
 use egg::*;
-use indexmap::IndexMap;
 
 use std::usize::MAX;
 use std::collections::HashMap;
@@ -9,43 +8,37 @@ use std::collections::HashMap;
 use egraph_serialize::NodeId;
 use std::f64::INFINITY;
 
-use crate::common::{Prop,DepthArea};
+use crate::common::{Prop,PropId,DepthArea};
+use crate::cost_model::HeCostModel;
 
-pub struct MultComplexity;
-impl egg::CostFunction<Prop> for MultComplexity {
-    type Cost = usize;
+/// Total gate cost under the active HE cost model.
+pub struct MultComplexity<'a>(pub &'a dyn HeCostModel);
+impl<'a> egg::CostFunction<Prop> for MultComplexity<'a> {
+    type Cost = f64;
     fn cost<C>(&mut self, enode: &Prop, mut costs: C) -> Self::Cost
     where
         C: FnMut(Id) -> Self::Cost,
     {
-        let op_cost = match enode {
-            Prop::And(..) => 1,
-            _ => 0,
-        };
+        let op_cost = self.0.gate_cost(&PropId::of(enode));
         enode.fold(op_cost, |sum, i| sum + costs(i))
     }
 }
 
-impl<N: Analysis<Prop>> LpCostFunction<Prop, N> for MultComplexity {
+impl<'a, N: Analysis<Prop>> LpCostFunction<Prop, N> for MultComplexity<'a> {
     fn node_cost(&mut self, _egraph: &EGraph<Prop, N>, _eclass: Id, enode: &Prop) -> f64 {
-        match enode {
-            Prop::And(..) => 1.0,
-            _ => 0.0,
-        }
+        self.0.gate_cost(&PropId::of(enode))
     }
 }
 
-pub struct MultDepth;
-impl egg::CostFunction<Prop> for MultDepth {
+/// Critical path length under the active HE cost model.
+pub struct MultDepth<'a>(pub &'a dyn HeCostModel);
+impl<'a> egg::CostFunction<Prop> for MultDepth<'a> {
     type Cost = usize;
     fn cost<C>(&mut self, enode: &Prop, mut costs: C) -> Self::Cost
     where
         C: FnMut(Id) -> Self::Cost,
     {
-        let op_cost = match enode {
-            Prop::And(..) => 1,
-            _ => 0,
-        };
+        let op_cost = self.0.gate_depth(&PropId::of(enode));
         op_cost + enode.fold(0, |max, i| max.max(costs(i)))
     }
 }
@@ -222,58 +215,6 @@ where
     (critical_path, real_network.join("\n"))
 }
 
-pub fn recexpr_traversal(expr: RecExpr<Prop>, out_net_to_eclass: &IndexMap<String, Id>) -> (u64, String) {
-    let mut network: Vec<String> = Vec::new();
-    let mut lvls: HashMap<Id,i32> = HashMap::new();
-    let mut ckt_md: u64 = 0;
-    for (id, p) in expr.as_ref().iter().enumerate() {
-        let mut netd = format!("n{id} = ");
-        let mut ok = true;
-        match p {
-            Prop::And([a, b]) => {
-                lvls.insert(Id::from(id), lvls[a].max(lvls[b]) + 1);
-                netd.push_str(format!("n{} * n{};", a, b).as_str());
-            }
-            Prop::Or([a, b]) => {
-                lvls.insert(Id::from(id), lvls[a].max(lvls[b]) + 1);
-                netd.push_str(format!("n{} + n{};", a, b).as_str());
-            }
-            Prop::Xor([a, b]) => {
-                lvls.insert(Id::from(id), lvls[a].max(lvls[b]));
-                netd.push_str(format!("(!n{} * n{}) + (n{} * !n{});", a, b, a, b).as_str());
-            }
-            Prop::Not(a) => {
-                lvls.insert(Id::from(id), lvls[a]);
-                netd.push_str(format!("!n{};", a).as_str());
-            }
-            Prop::Connect(a) => {
-                lvls.insert(Id::from(id), lvls[a]);
-                netd.push_str(format!("n{};", a).as_str());
-            }
-            Prop::Symbol(s) => {
-                lvls.insert(Id::from(id), 0);
-                netd.push_str(s.as_str());
-                netd.push(';');
-            }
-            Prop::Bool(b) => {
-                lvls.insert(Id::from(id), 0);
-                netd.push_str(if *b { "1;" } else { "0;" });
-            }
-            Prop::Concat(outs ) => {
-                for ((o_name, _), o_id) in out_net_to_eclass.iter().zip(outs.iter()) {
-                    ckt_md = ckt_md.max(lvls[o_id] as u64);
-                    network.push(format!("{} = n{};", o_name, o_id));            
-                }
-                ok = false;
-            }
-            _ => { ok = false; }
-        }
-        if ok {network.push(netd);}
-    }
-
-    (ckt_md,network.join("\n"))
-}
-
 #[allow(unused)]
 pub struct MixedCost<'a, L: Language, N: Analysis<L>> {
     pub egraph: &'a EGraph<L,N>,
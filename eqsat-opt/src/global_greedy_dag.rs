@@ -8,6 +8,7 @@ use rpds::HashTrieSet;
 
 use egraph_serialize::{ClassId,NodeId,Node,EGraph};
 use crate::extraction_ser::{ExtractionResult};
+use crate::cost_model::HeCostModel;
 
 #[derive(Debug, Clone, Copy)]
 struct Cost {
@@ -21,12 +22,27 @@ impl Cost {
     pub const INFINITY: Self = Self { depth: C_INFINITY, area: C_INFINITY };
     pub const ZERO: Self = Self { depth: C_ZERO, area: C_ZERO };
 
-    fn add_node_cost(self, c: egraph_serialize::Cost) -> Self {
+    fn add_node_cost(self, depth: egraph_serialize::Cost, area: egraph_serialize::Cost) -> Self {
         Self {
-            depth: self.depth + c,
-            area: self.area + c
+            depth: self.depth + depth,
+            area: self.area + area
         }
     }
+
+    /// Ordering key under `model`: leveled schemes rank by depth first,
+    /// the others by the model's combined scalar.
+    fn rank(&self, model: &dyn HeCostModel) -> (f64, f64) {
+        let (depth, area) = (self.depth.into_inner(), self.area.into_inner());
+        if model.depth_major() {
+            (depth, area)
+        } else {
+            (model.combine(depth as u64, area), area)
+        }
+    }
+
+    fn worse_than(&self, other: &Self, model: &dyn HeCostModel) -> bool {
+        self.rank(model) > other.rank(model)
+    }
 }
 
 impl std::ops::Add for Cost {
@@ -76,6 +92,7 @@ struct TermInfo {
     node: NodeId,
     eclass: ClassId,
     node_cost: egraph_serialize::Cost,
+    node_depth: egraph_serialize::Cost,
     total_cost: Cost,
     // store the set of reachable terms from this term
     reachable: Reachable,
@@ -104,9 +121,12 @@ impl TermDag {
         &mut self,
         node_id: NodeId,
         node: &Node,
+        node_depth: usize,
         children: Vec<TermId>,
         target: Cost,
+        model: &dyn HeCostModel,
     ) -> Option<TermId> {
+        let node_depth = NotNan::new(node_depth as f64).unwrap();
         let term = Term {
             op: node.op.clone(),
             children: children.clone(),
@@ -118,12 +138,13 @@ impl TermDag {
 
         if children.is_empty() {
             let next_id = self.nodes.len();
-            let node_cost = Cost::ZERO.add_node_cost(node.cost);
+            let node_cost = Cost::ZERO.add_node_cost(node_depth, node.cost);
             self.nodes.push(term.clone());
             self.info.push(TermInfo {
                 node: node_id,
                 eclass: node.eclass.clone(),
                 node_cost: node.cost,
+                node_depth,
                 total_cost: node_cost,
                 reachable: iter::once(node.eclass.clone()).collect(),
                 size: 1,
@@ -149,15 +170,15 @@ impl TermDag {
             let next_id = self.nodes.len();
 
             for child in children.iter() {
-                if cost.add_node_cost(node.cost) > target {
+                if cost.add_node_cost(node_depth, node.cost).worse_than(&target, model) {
                     return None;
                 }
                 let child_cost = self.get_cost(&mut reachable, *child);
                 cost += child_cost;
             }
-            cost = cost.add_node_cost(node.cost);
+            cost = cost.add_node_cost(node_depth, node.cost);
 
-            if cost > target {
+            if cost.worse_than(&target, model) {
                 return None;
             }
 
@@ -166,6 +187,7 @@ impl TermDag {
             self.info.push(TermInfo {
                 node: node_id,
                 node_cost: node.cost,
+                node_depth,
                 eclass: node.eclass.clone(),
                 total_cost: cost,
                 reachable,
@@ -195,7 +217,7 @@ impl TermDag {
                 let child_cost = self.get_cost(shared, *child);
                 cost += child_cost;
             }
-            cost = cost.add_node_cost(self.node_cost(id));
+            cost = cost.add_node_cost(self.info[id].node_depth, self.node_cost(id));
             *shared = shared.insert(eclass);
             cost
         }
@@ -210,7 +232,7 @@ impl TermDag {
     }
 }
 
-pub fn mc_extract(egraph: &EGraph, roots: &[ClassId], locked: HashMap<ClassId, NodeId>) -> ExtractionResult {
+pub fn mc_extract(egraph: &EGraph, roots: &[ClassId], locked: HashMap<ClassId, NodeId>, model: &dyn HeCostModel) -> ExtractionResult {
     let mut keep_going = true;
 
     let nodes = egraph.nodes.clone();
@@ -241,10 +263,10 @@ pub fn mc_extract(egraph: &EGraph, roots: &[ClassId], locked: HashMap<ClassId, N
                 .map(|id| termdag.total_cost(*id))
                 .unwrap_or(Cost::INFINITY);
 
-            if let Some(candidate) = termdag.make(node_id.clone(), node, children, old_cost) {
+            if let Some(candidate) = termdag.make(*node_id, node, model.node_depth(egraph, node), children, old_cost, model) {
                 let cadidate_cost = termdag.total_cost(candidate);
 
-                if cadidate_cost < old_cost {
+                if old_cost.worse_than(&cadidate_cost, model) {
                     best_in_class.insert(node.eclass.clone(), candidate);
                     keep_going = true;
                 }
@@ -0,0 +1,233 @@
+// ILP extraction over the serialized e-graph.
+// The model is built once and re-solved under different depth bounds, starting from the best known solution.
+use std::collections::{HashMap, HashSet};
+
+use coin_cbc::{Col, Model, Sense, Solution};
+use egraph_serialize::{ClassId, EGraph, NodeId};
+use indexmap::IndexMap;
+
+use crate::cost_model::HeCostModel;
+use crate::extraction_ser::{levelize, ExtractionResult};
+
+/// Levels an extraction may go past the start's MD when every solve has a depth bound and none
+/// asks for more. The level constraints use the MD limit as their big-M, so it is kept as tight
+/// as possible.
+pub const MD_SLACK: usize = 2;
+
+struct ClassVars {
+    active: Col,
+    /// Depth of the class's value, in the model's levels
+    level: Col,
+    /// Topological position; keeps the selection acyclic even through gates that add no depth
+    order: Col,
+}
+
+pub struct IlpExtractor<'a> {
+    egraph: &'a EGraph,
+    he_model: &'a dyn HeCostModel,
+    model: Model,
+    nodes: IndexMap<NodeId, Col>,
+    classes: IndexMap<ClassId, ClassVars>,
+    md: Col,
+    max_md: usize,
+    roots: Vec<ClassId>,
+    /// Levels by which some roots must be ready, on top of the MD bound
+    required: HashMap<ClassId, usize>,
+    /// Known solutions as (MD, cost, extraction), used as MIP starts and kept on timeout
+    starts: Vec<(usize, f64, ExtractionResult)>,
+}
+
+impl<'a> IlpExtractor<'a> {
+    /// Builds the ILP around a known extraction `start`, which also becomes the first MIP start.
+    /// `max_bound` is the largest depth bound it will be solved under, `None` if some solve is
+    /// unbounded. With a bound, extractions deeper than the start's MD plus `MD_SLACK` (or
+    /// `max_bound`, if larger) are not considered.
+    pub fn new(
+        egraph: &'a EGraph,
+        roots: &[ClassId],
+        he_model: &'a dyn HeCostModel,
+        start: &ExtractionResult,
+        max_bound: Option<usize>,
+        time_limit: f64,
+    ) -> Self {
+        let mut model = Model::default();
+        model.set_parameter("seconds", &time_limit.to_string());
+        model.set_obj_sense(Sense::Minimize);
+
+        let choices: HashMap<ClassId, NodeId> = start.iter().map(|(c, (_, n))| (*c, *n)).collect();
+        let start = levelize(egraph, roots, &choices, he_model);
+        let max_md = match (&start, max_bound) {
+            (Some((md, _, _)), Some(bound)) => (md + MD_SLACK).max(bound),
+            // no extraction can be deeper than the deepest node of every class stacked up
+            _ => egraph
+                .classes()
+                .values()
+                .map(|c| c.nodes.iter().map(|n| he_model.node_depth(egraph, &egraph[n])).max().unwrap_or(0))
+                .sum(),
+        };
+        let n_classes = egraph.classes().len() as f64;
+
+        let mut classes: IndexMap<ClassId, ClassVars> = IndexMap::new();
+        for cid in egraph.classes().keys() {
+            let active = model.add_binary();
+            let level = model.add_col();
+            model.set_col_upper(level, max_md as f64);
+            let order = model.add_col();
+            model.set_col_upper(order, n_classes);
+            classes.insert(*cid, ClassVars { active, level, order });
+        }
+
+        let mut nodes: IndexMap<NodeId, Col> = IndexMap::new();
+        for (node_id, node) in &egraph.nodes {
+            let x = model.add_binary();
+            model.set_obj_coeff(x, he_model.node_cost(egraph, node));
+            nodes.insert(*node_id, x);
+        }
+
+        for (cid, class) in egraph.classes() {
+            // an active class picks exactly one of its nodes
+            let row = model.add_row();
+            model.set_row_equal(row, 0.0);
+            model.set_weight(row, classes[cid].active, -1.0);
+            for n in &class.nodes {
+                model.set_weight(row, nodes[n], 1.0);
+            }
+        }
+
+        for (node_id, node) in &egraph.nodes {
+            let x = nodes[node_id];
+            let c = &classes[&node.eclass];
+            let depth = he_model.node_depth(egraph, node) as f64;
+            let child_classes: HashSet<&ClassId> = node.children.iter().map(|n| egraph.nid_to_cid(n)).collect();
+            if child_classes.contains(&node.eclass) {
+                model.set_col_upper(x, 0.0);
+                continue;
+            }
+            if child_classes.is_empty() && depth > 0.0 {
+                let row = model.add_row();
+                model.set_row_lower(row, 0.0);
+                model.set_weight(row, c.level, 1.0);
+                model.set_weight(row, x, -depth);
+            }
+            for k in child_classes {
+                let k = &classes[k];
+                // picking the node activates the child
+                let row = model.add_row();
+                model.set_row_upper(row, 0.0);
+                model.set_weight(row, x, 1.0);
+                model.set_weight(row, k.active, -1.0);
+                // level(c) >= level(k) + depth when picked
+                let big_m = max_md as f64;
+                let row = model.add_row();
+                model.set_row_lower(row, depth - big_m);
+                model.set_weight(row, c.level, 1.0);
+                model.set_weight(row, k.level, -1.0);
+                model.set_weight(row, x, -big_m);
+                // order(c) >= order(k) + 1 when picked
+                let big_m = n_classes + 1.0;
+                let row = model.add_row();
+                model.set_row_lower(row, 1.0 - big_m);
+                model.set_weight(row, c.order, 1.0);
+                model.set_weight(row, k.order, -1.0);
+                model.set_weight(row, x, -big_m);
+            }
+        }
+
+        let md = model.add_col();
+        model.set_col_upper(md, max_md as f64);
+        for root in roots {
+            model.set_col_lower(classes[root].active, 1.0);
+            let row = model.add_row();
+            model.set_row_lower(row, 0.0);
+            model.set_weight(row, md, 1.0);
+            model.set_weight(row, classes[root].level, -1.0);
+        }
+
+        Self {
+            egraph,
+            he_model,
+            model,
+            nodes,
+            classes,
+            md,
+            max_md,
+            roots: roots.to_vec(),
+            required: HashMap::new(),
+            starts: start.into_iter().collect(),
+        }
+    }
+
+    pub fn set_time_limit(&mut self, time_limit: f64) {
+        self.model.set_parameter("seconds", &time_limit.to_string());
+    }
+
+    /// Requires each root in `required` to be ready by its level.
+    pub fn set_required(&mut self, required: &HashMap<ClassId, usize>) {
+        for (root, level) in required {
+            self.model.set_col_upper(self.classes[root].level, *level as f64);
+        }
+        self.required = required.clone();
+    }
+
+    /// Solves for the cheapest extraction with MD at most `depth_bound` (at most the MD limit).
+    /// Never returns anything worse than the best known start that meets the bound,
+    /// which is also what is returned if the solver times out without an incumbent.
+    pub fn solve(&mut self, depth_bound: Option<usize>) -> Option<ExtractionResult> {
+        let bound = depth_bound.map_or(self.max_md, |b| b.min(self.max_md));
+        self.model.set_col_upper(self.md, bound as f64);
+
+        let best_start = self
+            .starts
+            .iter()
+            .filter(|(md, _, start)| *md <= bound && self.required.iter().all(|(r, l)| start[r].0 <= *l))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .cloned();
+        if let Some((_, _, start)) = &best_start {
+            self.set_initial_solution(start);
+        }
+
+        let solution = self.model.solve();
+        let solved = if solution.raw().is_proven_infeasible() { None } else { self.read_solution(&solution) };
+        match (solved, best_start) {
+            (Some(s), Some(b)) if b.1 < s.1 => Some(b.2),
+            (Some(s), _) if s.0 <= bound => {
+                self.starts.push(s.clone());
+                Some(s.2)
+            }
+            (_, b) => b.map(|(_, _, r)| r),
+        }
+    }
+
+    fn set_initial_solution(&mut self, start: &ExtractionResult) {
+        let chosen: HashSet<&NodeId> = start.values().map(|(_, n)| n).collect();
+        for (node_id, x) in &self.nodes {
+            self.model.set_col_initial_solution(*x, if chosen.contains(node_id) { 1.0 } else { 0.0 });
+        }
+        let mut md = 0;
+        for (cid, vars) in &self.classes {
+            let (level, order, active) = match start.get_index_of(cid) {
+                Some(i) => (start[i].0, i, 1.0),
+                None => (0, 0, 0.0),
+            };
+            self.model.set_col_initial_solution(vars.active, active);
+            self.model.set_col_initial_solution(vars.level, level as f64);
+            self.model.set_col_initial_solution(vars.order, order as f64);
+            if self.roots.contains(cid) {
+                md = md.max(level);
+            }
+        }
+        self.model.set_col_initial_solution(self.md, md as f64);
+    }
+
+    fn read_solution(&self, solution: &Solution) -> Option<(usize, f64, ExtractionResult)> {
+        let mut choices: HashMap<ClassId, NodeId> = HashMap::new();
+        for (cid, class) in self.egraph.classes() {
+            if solution.col(self.classes[cid].active) < 0.5 {
+                continue;
+            }
+            let node = class.nodes.iter().find(|n| solution.col(self.nodes[*n]) > 0.5)?;
+            choices.insert(*cid, *node);
+        }
+        levelize(self.egraph, &self.roots, &choices, self.he_model)
+    }
+}
@@ -0,0 +1,243 @@
+// Rules learned from traces: every U (union) and O (output replacement) step of a trace says
+// that two nodes of that circuit are equivalent. Cutting both cones a few gates deep and turning
+// the cut into pattern variables gives a candidate rule, which is kept if it holds for every
+// value of the cut, i.e. independently of the rest of the circuit.
+use std::collections::HashMap;
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use crate::trace::{Insn, Lit, Trace};
+
+/// A learned rule: lhs, rhs and the (AND count, AND depth) of each side.
+type LearnedRule = (String, String, (usize, usize), (usize, usize));
+
+/// (lhs, rhs) -> (times seen, lhs cost, rhs cost)
+type RuleCounts = IndexMap<(String, String), (usize, (usize, usize), (usize, usize))>;
+
+/// Cuts are checked with one-word truth tables.
+const MAX_LEAVES: usize = 6;
+
+const VAR_NAMES: [&str; MAX_LEAVES] = ["a", "b", "c", "d", "e", "f"];
+
+enum Pat {
+    Leaf(usize),
+    False,
+    Not(Box<Pat>),
+    And(Box<Pat>, Box<Pat>),
+    Xor(Box<Pat>, Box<Pat>),
+}
+
+impl Pat {
+    fn not(self) -> Pat {
+        match self {
+            Pat::Not(p) => *p,
+            p => Pat::Not(Box::new(p)),
+        }
+    }
+
+    fn tt(&self, leaves: usize) -> u64 {
+        match self {
+            Pat::Leaf(i) => (0..1u64 << leaves).filter(|a| (a >> i) & 1 == 1).fold(0, |tt, a| tt | (1 << a)),
+            Pat::False => 0,
+            Pat::Not(p) => !p.tt(leaves),
+            Pat::And(a, b) => a.tt(leaves) & b.tt(leaves),
+            Pat::Xor(a, b) => a.tt(leaves) ^ b.tt(leaves),
+        }
+    }
+
+    /// (AND count, AND depth) of the tree.
+    fn cost(&self) -> (usize, usize) {
+        match self {
+            Pat::Leaf(_) | Pat::False => (0, 0),
+            Pat::Not(p) => p.cost(),
+            Pat::And(a, b) | Pat::Xor(a, b) => {
+                let and = matches!(self, Pat::And(..)) as usize;
+                let ((ac, ad), (bc, bd)) = (a.cost(), b.cost());
+                (ac + bc + and, ad.max(bd) + and)
+            }
+        }
+    }
+
+    fn leaves(&self, out: &mut Vec<usize>) {
+        match self {
+            Pat::Leaf(i) if !out.contains(i) => out.push(*i),
+            Pat::Leaf(_) | Pat::False => {}
+            Pat::Not(p) => p.leaves(out),
+            Pat::And(a, b) | Pat::Xor(a, b) => {
+                a.leaves(out);
+                b.leaves(out);
+            }
+        }
+    }
+
+    /// Rule-file pattern, leaf `i` being the variable `names[i]`.
+    fn sexpr(&self, names: &HashMap<usize, &str>) -> String {
+        match self {
+            Pat::Leaf(i) => format!("?{}", names[i]),
+            Pat::False => "false".to_string(),
+            Pat::Not(p) => format!("(! {})", p.sexpr(names)),
+            Pat::And(a, b) => format!("(* {} {})", a.sexpr(names), b.sexpr(names)),
+            Pat::Xor(a, b) => format!("(^ {} {})", a.sexpr(names), b.sexpr(names)),
+        }
+    }
+}
+
+/// Gates of the traced circuit; every other node but the constant 0 is an input.
+struct Network {
+    gates: HashMap<usize, (bool, Lit, Lit)>,
+}
+
+impl Network {
+    /// Nodes `depth` gates below `node`, and inputs above that.
+    fn cut(&self, node: usize, depth: usize, leaves: &mut Vec<usize>) {
+        if node == 0 {
+            return;
+        }
+        match self.gates.get(&node) {
+            Some((_, a, b)) if depth > 0 => {
+                self.cut(a.node, depth - 1, leaves);
+                self.cut(b.node, depth - 1, leaves);
+            }
+            _ if !leaves.contains(&node) => leaves.push(node),
+            _ => {}
+        }
+    }
+
+    fn pattern(&self, lit: Lit, leaves: &[usize]) -> Pat {
+        let pat = if lit.node == 0 {
+            Pat::False
+        } else if let Some(i) = leaves.iter().position(|l| *l == lit.node) {
+            Pat::Leaf(i)
+        } else {
+            let (xor, a, b) = self.gates[&lit.node];
+            let (a, b) = (Box::new(self.pattern(a, leaves)), Box::new(self.pattern(b, leaves)));
+            if xor { Pat::Xor(a, b) } else { Pat::And(a, b) }
+        };
+        if lit.compl { pat.not() } else { pat }
+    }
+
+    /// The rule `old => new` on the shallowest cut (at most `max_depth` gates deep) on which it
+    /// holds, with variables named in order of appearance.
+    fn generalize(&self, old: Lit, new: Lit, max_depth: usize) -> Option<LearnedRule> {
+        for depth in 1..=max_depth {
+            let mut leaves = Vec::new();
+            self.cut(old.node, depth, &mut leaves);
+            self.cut(new.node, depth, &mut leaves);
+            if leaves.len() > MAX_LEAVES {
+                return None;
+            }
+            let (lhs, rhs) = (self.pattern(old, &leaves), self.pattern(new, &leaves));
+            // an lhs without a gate would match everything
+            if matches!(lhs, Pat::Leaf(_) | Pat::False) || matches!(&lhs, Pat::Not(p) if matches!(**p, Pat::Leaf(_) | Pat::False)) {
+                return None;
+            }
+            let (mut lhs_leaves, mut rhs_leaves) = (Vec::new(), Vec::new());
+            lhs.leaves(&mut lhs_leaves);
+            rhs.leaves(&mut rhs_leaves);
+            let mask = if leaves.len() == MAX_LEAVES { !0 } else { (1u64 << (1 << leaves.len())) - 1 };
+            let holds = (lhs.tt(leaves.len()) ^ rhs.tt(leaves.len())) & mask == 0;
+            if !holds || !rhs_leaves.iter().all(|l| lhs_leaves.contains(l)) {
+                continue;
+            }
+            let names: HashMap<usize, &str> = lhs_leaves.iter().enumerate().map(|(i, l)| (*l, VAR_NAMES[i])).collect();
+            let (lhs_s, rhs_s) = (lhs.sexpr(&names), rhs.sexpr(&names));
+            if lhs_s == rhs_s {
+                return None;
+            }
+            return Some((lhs_s, rhs_s, lhs.cost(), rhs.cost()));
+        }
+        None
+    }
+}
+
+/// Writes the rules learned from the U and O steps of `trace` to `outfile`, most frequent first.
+/// Steps giving the same rule text after renaming count as one rule; commuted variants of a rule
+/// have a different text and are written as separate rules.
+pub fn learn_rules(trace: &Trace, max_depth: usize, outfile: &Path) {
+    let insns = &trace.insns;
+    let net = Network {
+        gates: insns
+            .iter()
+            .filter_map(|insn| match insn {
+                Insn::Gate { n, xor, a, b } => Some((*n, (*xor, *a, *b))),
+                _ => None,
+            })
+            .collect(),
+    };
+
+    let mut rules: RuleCounts = IndexMap::new();
+    let mut pos: Vec<Lit> = Vec::new();
+    let mut steps = 0;
+    for insn in insns {
+        let (old, new) = match insn {
+            Insn::Gate { .. } => continue,
+            Insn::Output { ind, lit } if *ind >= pos.len() => {
+                pos.push(*lit);
+                continue;
+            }
+            Insn::Output { ind, lit } => (std::mem::replace(&mut pos[*ind], *lit), *lit),
+            Insn::Union { c, lit } => (Lit { node: *c, compl: false }, *lit),
+        };
+        steps += 1;
+        if let Some((lhs, rhs, lhs_cost, rhs_cost)) = net.generalize(old, new, max_depth) {
+            rules.entry((lhs, rhs)).or_insert((0, lhs_cost, rhs_cost)).0 += 1;
+        }
+    }
+    rules.sort_by(|_, (a, ..), _, (b, ..)| b.cmp(a));
+
+    let mut out = format!("# learned by eqsat-opt learn-rules --cone-depth {} from {} trace steps\n", max_depth, steps);
+    for (i, ((lhs, rhs), (seen, (lmc, lmd), (rmc, rmd)))) in rules.iter().enumerate() {
+        out.push_str(&format!("learned{}:{}=>{}  # seen {}, mc {}->{}, md {}->{}\n", i, lhs, rhs, seen, lmc, rmc, lmd, rmd));
+    }
+    println!("{} rules from {} trace steps", rules.len(), steps);
+    std::fs::write(outfile, out).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (rule, times seen) learned from the trace `contents`.
+    fn learn(name: &str, contents: &str, max_depth: usize) -> Vec<(String, usize)> {
+        let outfile = std::env::temp_dir().join(format!("eqsat-opt-learn-{}-{}.rules", std::process::id(), name));
+        learn_rules(&Trace::parse(contents).unwrap(), max_depth, &outfile);
+        let rules = std::fs::read_to_string(&outfile).unwrap();
+        std::fs::remove_file(&outfile).unwrap();
+        rules
+            .lines()
+            .skip(1)
+            .map(|l| {
+                let (rule, comment) = l.split_once(':').unwrap().1.split_once("  # seen ").unwrap();
+                (rule.to_string(), comment.split_once(',').unwrap().0.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn learns_absorption() {
+        // 4 = 1 * (1 * 2) is merged with 3 = 1 * 2
+        let rules = learn("absorption", "A 3 0 1 0 2\nA 4 0 1 0 3\nU 4 0 3\n", 2);
+        assert_eq!(rules, vec![("(* ?a (* ?a ?b))=>(* ?a ?b)".to_string(), 1)]);
+    }
+
+    #[test]
+    fn rejects_cuts_the_step_does_not_hold_on() {
+        // (1 * 2) * (1 * !2) is false, but not for every value of the cut {1 * 2, 1 * !2}
+        let trace = "A 3 0 1 0 2\nA 4 0 1 1 2\nA 5 0 3 0 4\nU 5 0 0\n";
+        assert_eq!(learn("shallow", trace, 1), vec![]);
+        assert_eq!(learn("deep", trace, 2), vec![("(* (* ?a ?b) (* ?a (! ?b)))=>false".to_string(), 1)]);
+    }
+
+    #[test]
+    fn rules_are_deduplicated_after_renaming() {
+        let trace = "A 3 0 1 0 2\nA 4 0 1 0 3\nU 4 0 3\n\
+                     A 5 0 2 0 1\nA 6 0 2 0 5\nU 6 0 5\n\
+                     A 7 0 5 0 2\nU 7 0 5\n";
+        let rules = learn("renaming", trace, 2);
+        assert_eq!(
+            rules,
+            vec![("(* ?a (* ?a ?b))=>(* ?a ?b)".to_string(), 2), ("(* (* ?a ?b) ?a)=>(* ?a ?b)".to_string(), 1)]
+        );
+    }
+}
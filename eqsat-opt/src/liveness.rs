@@ -0,0 +1,135 @@
+// Live ciphertexts of a written network, as he-eval's register allocator sees them,
+// and an equation order that keeps their peak low.
+use std::collections::{HashMap, HashSet};
+
+use egraph_serialize::{ClassId, EGraph};
+use indexmap::IndexMap;
+
+use crate::common::PropId;
+use crate::cost_model::{HeCostModel, NetworkCost};
+use crate::extraction_ser::{dag_network_writer, node_rhs, ExtractionResult};
+use crate::serde::decode_op_string;
+
+fn output_classes(out_net_to_eclass: &IndexMap<String, egg::Id>) -> HashSet<ClassId> {
+    out_net_to_eclass.values().map(|id| ClassId::new(Into::<u32>::into(*id))).collect()
+}
+
+/// Most values live at once when the equations of `result` are evaluated in its order.
+/// A value lives from its equation to its last use; inputs are live from the start
+/// and outputs until the end, like he-eval's register allocation.
+pub fn peak_live(egraph: &EGraph, result: &ExtractionResult, out_net_to_eclass: &IndexMap<String, egg::Id>) -> u64 {
+    let n = result.len();
+    let outputs = output_classes(out_net_to_eclass);
+    let mut last_use: HashMap<&ClassId, usize> = HashMap::new();
+    for (i, (_, (_, node_id))) in result.iter().enumerate() {
+        for child in &egraph[node_id].children {
+            last_use.insert(egraph.nid_to_cid(child), i);
+        }
+    }
+
+    // +1 where a value becomes live, -1 where it dies
+    let mut delta: Vec<i64> = vec![0; n + 1];
+    for (i, (class, (_, node_id))) in result.iter().enumerate() {
+        let start = if matches!(decode_op_string(&egraph[node_id].op), PropId::Sym) { 0 } else { i };
+        let end = if outputs.contains(class) { n } else { last_use.get(class).copied().unwrap_or(n) };
+        delta[start] += 1;
+        delta[end] -= 1;
+    }
+    let mut live = 0;
+    let mut peak = 0;
+    for d in &delta[..n] {
+        live += d;
+        peak = peak.max(live);
+    }
+    peak as u64
+}
+
+/// Writes the network with its equations ordered to keep few values live at once.
+/// Classes are emitted depth-first, visiting the operand that needs the most registers first
+/// (Sethi-Ullman numbering, with shared operands counted as if they were not).
+pub fn min_peak_writer(
+    egraph: &EGraph,
+    cost_analysis: &mut ExtractionResult,
+    out_net_to_eclass: &IndexMap<String, egg::Id>,
+    model: &dyn HeCostModel,
+) -> (NetworkCost, String) {
+    // the DAG writer settles the cost and leaves only the used classes, in topological order
+    let (mut cost, _) = dag_network_writer(egraph, cost_analysis, out_net_to_eclass, model);
+
+    let children = |class: &ClassId| -> Vec<ClassId> {
+        egraph[&cost_analysis[class].1].children.iter().map(|c| *egraph.nid_to_cid(c)).collect()
+    };
+    let mut need: HashMap<ClassId, usize> = HashMap::new();
+    for class in cost_analysis.keys() {
+        let mut child_needs: Vec<usize> = children(class).iter().map(|c| need[c]).collect();
+        child_needs.sort_unstable_by(|a, b| b.cmp(a));
+        let n = child_needs.iter().enumerate().map(|(i, c)| c + i).max().unwrap_or(1);
+        need.insert(*class, n);
+    }
+    let by_need = |classes: &mut Vec<ClassId>| classes.sort_by(|a, b| need[b].cmp(&need[a]));
+
+    let mut roots: Vec<ClassId> = out_net_to_eclass.values().map(|id| ClassId::new(Into::<u32>::into(*id))).collect();
+    by_need(&mut roots);
+    let mut order: ExtractionResult = IndexMap::new();
+    // the stack pops the neediest operand first
+    let mut stack: Vec<(ClassId, bool)> = roots.into_iter().rev().map(|c| (c, false)).collect();
+    while let Some((class, expanded)) = stack.pop() {
+        if order.contains_key(&class) {
+            continue;
+        }
+        if expanded {
+            order.insert(class, cost_analysis[&class]);
+        } else {
+            stack.push((class, true));
+            let mut operands = children(&class);
+            by_need(&mut operands);
+            stack.extend(operands.into_iter().rev().map(|c| (c, false)));
+        }
+    }
+
+    let mut network: Vec<String> = order
+        .iter()
+        .map(|(class, (_, node_id))| format!("n{} = {}", class, node_rhs(&egraph[node_id], model)))
+        .collect();
+    for (o_name, o_id) in out_net_to_eclass.iter() {
+        network.push(format!("{} = n{};", o_name, o_id));
+    }
+    *cost_analysis = order;
+    cost.peak_live = peak_live(egraph, cost_analysis, out_net_to_eclass);
+    (cost, network.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Prop, PropAnalysis};
+    use crate::cost_model::LeveledBgv;
+    use crate::{global_greedy_dag, serde};
+    use egg::RecExpr;
+
+    /// Peak of the network written for `expr` with its only output named `o`.
+    fn peak(expr: &str) -> u64 {
+        let expr: RecExpr<Prop> = expr.parse().unwrap();
+        let mut egraph: egg::EGraph<Prop, PropAnalysis> = Default::default();
+        let root = egraph.add_expr(&expr);
+        egraph.rebuild();
+        let ser = serde::serialize_in_mem(&egraph, [&root], &LeveledBgv);
+        let out_net_to_eclass: IndexMap<String, egg::Id> = IndexMap::from([("o".to_string(), root)]);
+        let mut result = global_greedy_dag::mc_extract(&ser, &ser.root_eclasses, HashMap::new(), &LeveledBgv);
+        dag_network_writer(&ser, &mut result, &out_net_to_eclass, &LeveledBgv);
+        peak_live(&ser, &result, &out_net_to_eclass)
+    }
+
+    #[test]
+    fn operands_are_freed_at_their_last_use() {
+        // he-eval: a[0,2) b[1,3) na[2,4) nb[3,4) n[4,5) o[5,6); an interval ending where another
+        // starts does not interfere with it, so the gate reuses an operand's register
+        assert_eq!(peak("(* a b)"), 2);
+    }
+
+    #[test]
+    fn inputs_are_live_from_the_start() {
+        // all three inputs are defined before the first equation
+        assert_eq!(peak("(* (* a b) c)"), 3);
+    }
+}
@@ -1,5 +1,5 @@
 use clap::{Parser, Subcommand};
-use egg::{rewrite as rw, *};
+use egg::*;
 use egraph_serialize::NodeId;
 use env_logger::init;
 use extraction_unser::MultDepth;
@@ -13,57 +13,56 @@ use std::f32::consts::E;
 use std::io::Seek;
 use std::io::Write;
 use std::ops::Index;
-use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+mod ablate;
+mod anytime;
+mod bootstrap;
 mod common;
+mod constraints;
+mod cost_model;
+mod explain;
 mod extraction_ser;
 mod extraction_unser;
 mod global_greedy_dag;
+mod ilp_extract;
+mod learn;
+mod liveness;
 mod md_mc_balanced_extract;
 mod md_slack;
+mod pareto;
+mod portfolio;
+mod rule_stats;
+mod rules;
+mod schedule;
+mod scheduler;
 mod serde;
+mod stochastic;
+mod sweep;
+mod trace;
 mod traverse;
 
-use common::Prop;
+use common::{Prop, PropAnalysis};
+use cost_model::{CostProfile, DepthWeighted, HeCostModel, MemoryWeighted, NetworkCost, SimdBatched, Timed, XorWeighted};
 
 ///////////////////////////////////////
 // Saturation setup (input parsing) //
 /////////////////////////////////////
 
-fn parse_rules(rules: &mut Vec<Rewrite<Prop, ()>>, rules_string: &str) {
-    for line in rules_string.lines() {
-        let mut split = line.split(":");
-        let name = split.next().unwrap();
-        let body = split.next();
-        if body.is_none() {
-            panic!(
-                "malformed rule file: expected \"<name>:<lhs>[=>|<=>]<rhs>\"; got {}",
-                line
-            );
-        }
-        let body = body.unwrap();
-        if body.contains("<=>") {
-            let mut split = body.split("<=>");
-            let lhs: Pattern<Prop> = split.next().unwrap().parse().unwrap();
-            let rhs: Pattern<Prop> = split.next().unwrap().parse().unwrap();
-            rules.extend(rw!({name}; {lhs.clone()} <=> {rhs.clone()}));
-        } else if line.contains("=>") {
-            let mut split = body.split("=>");
-            let lhs: Pattern<Prop> = split.next().unwrap().parse().unwrap();
-            let rhs: Pattern<Prop> = split.next().unwrap().parse().unwrap();
-            rules.push(rw!({name}; {lhs} => {rhs}));
-        } else {
-            panic!(
-                "malformed rule file: expected \"<name>:<lhs>[=>|<=>]<rhs>\"; got {}",
-                line
-            );
-        }
-    }
-}
-
-fn egraph_init_from_pis(innodes: &str) -> (EGraph<Prop, ()>, HashMap<String, Id>) {
-    let mut egraph = EGraph::<Prop, ()>::default();
+/// Inputs named in `public` are plaintext; all others are encrypted. Class depths are kept
+/// under `cost_model`. With `explain`, the e-graph records why classes were merged.
+fn egraph_init_from_pis(
+    innodes: &str,
+    public: &HashSet<String>,
+    cost_model: Arc<dyn HeCostModel>,
+    explain: bool,
+) -> (EGraph<Prop, PropAnalysis>, HashMap<String, Id>) {
+    let egraph = EGraph::new(PropAnalysis::new(public.iter().map(Symbol::new).collect(), cost_model));
+    let mut egraph = if explain { egraph.with_explanations_enabled() } else { egraph };
 
     let mut ckt_node_to_eclass: HashMap<String, Id> = HashMap::new();
     ckt_node_to_eclass.insert("true".to_string(), egraph.add(Prop::Bool(true)));
@@ -79,28 +78,17 @@ fn egraph_init_from_pis(innodes: &str) -> (EGraph<Prop, ()>, HashMap<String, Id>
 fn egraph_from_seqn_trace(
     innodes: &str,
     outnodes: &str,
-    trace: &str
+    trace: &trace::Trace,
+    public: &HashSet<String>,
+    cost_model: Arc<dyn HeCostModel>,
+    explain: bool,
 ) -> EqsatOptimizer {
-    let (mut egraph, _) = egraph_init_from_pis(innodes);
+    let (mut egraph, _) = egraph_init_from_pis(innodes, public, cost_model.clone(), explain);
     let num_pis = innodes.split(" ").count();
 
-    let mut committed: Vec<&str> = Vec::new();
-    let mut temp: Vec<&str> = Vec::new();
-    for insn in trace.lines() {
-        if insn == "COMMIT" {
-            committed.append(&mut temp);
-        } else if insn == "FORGET" {
-            if !committed.is_empty() {
-                temp.clear();
-            }
-        } else if insn.starts_with("COM") {
-            continue;
-        } else {
-            temp.push(insn);
-        }
-    }
-    committed.append(&mut temp);
     let mut pos: Vec<Id> = Vec::new();
+    // outputs as first written, before later O instructions replace them
+    let mut originals: Vec<Id> = Vec::new();
     let mut index_map: HashMap<usize, Id> = HashMap::new();
     let mut prev_index_map: HashMap<usize, Id> = HashMap::new();
     // mapping to false e-class
@@ -108,80 +96,57 @@ fn egraph_from_seqn_trace(
     for i in 0..num_pis {
         index_map.insert(i + 1, Id::from(2 + i as usize));
     }
-    let mut ind = 1;
-    for insn_s in committed {
-        let mut insn = insn_s.split(" ");
-        let op = insn.next().unwrap();
-        match op {
-            "X" | "A" => {
-                let n: usize = insn.next().unwrap().parse().unwrap();
+    // a node, complemented if need be
+    let lit = |egraph: &mut EGraph<Prop, PropAnalysis>, index_map: &HashMap<usize, Id>, lit: trace::Lit| {
+        if lit.compl {
+            egraph.add_uncanonical(Prop::Not(index_map[&lit.node]))
+        } else {
+            index_map[&lit.node]
+        }
+    };
+    for (insn, line) in trace.insns.iter().copied().zip(&trace.lines) {
+        egraph.analysis.cause = Some(format!("trace line {} ({})", line, insn));
+        match insn {
+            trace::Insn::Gate { n, xor, a, b } => {
                 // canonical nodes should never be reused
                 assert!(!index_map.contains_key(&n));
-                let ac: u32 = insn.next().unwrap().parse().unwrap();
-                let a: usize = insn.next().unwrap().parse().unwrap();
-                let bc: u32 = insn.next().unwrap().parse().unwrap();
-                let b: usize = insn.next().unwrap().parse().unwrap();
-                let a = if ac == 1 {
-                    egraph.add(Prop::Not(index_map[&a]))
+                let a = lit(&mut egraph, &index_map, a);
+                let b = lit(&mut egraph, &index_map, b);
+                let nid = if xor {
+                    egraph.add_uncanonical(Prop::Xor([a, b]))
                 } else {
-                    if !index_map.contains_key(&a) {
-                        dbg!(&a);
-                    }
-                    index_map[&a]
-                };
-                let b = if bc == 1 {
-                    egraph.add(Prop::Not(index_map[&b]))
-                } else {
-                    index_map[&b]
-                };
-                let nid = if op == "X" {
-                    egraph.add(Prop::Xor([a, b]))
-                } else {
-                    egraph.add(Prop::And([a, b]))
+                    egraph.add_uncanonical(Prop::And([a, b]))
                 };
                 index_map.insert(n, nid);
             }
-            "O" => {
-                let ind: usize = insn.next().unwrap().parse().unwrap();
-                let compl: u32 = insn.next().unwrap().parse().unwrap();
-                let po_n: usize = insn.next().unwrap().parse().unwrap();
-                let po_n = if compl == 1 {
-                    egraph.add(Prop::Not(index_map[&po_n]))
-                } else {
-                    index_map[&po_n]
-                };
+            trace::Insn::Output { ind, lit: po_lit } => {
+                let po_n = lit(&mut egraph, &index_map, po_lit);
                 if pos.len() <= ind {
                     assert!(ind == pos.len());
                     pos.push(po_n);
+                    originals.push(po_n);
                 } else {
                     //let po_n_c = egraph.add(Prop::Connect(po_n));
-                    egraph.union(pos[ind], po_n);
+                    egraph.union_trusted(pos[ind], po_n, format!("trace: {}", insn));
                     pos[ind] = po_n;
                 }
             }
-            "U" => {
-                let c: usize = insn.next().unwrap().parse().unwrap();
-                let compl: u32 = insn.next().unwrap().parse().unwrap();
-                let new_n: usize = insn.next().unwrap().parse().unwrap();
+            trace::Insn::Union { c, lit: new_lit } => {
                 let c = index_map[&c];
-                let new_n_id = if compl == 1 {
-                    assert!(index_map[&new_n] != c);
-                    egraph.add(Prop::Not(index_map[&new_n]))
-                } else {
-                    index_map[&new_n]
-                };
+                if new_lit.compl {
+                    assert!(index_map[&new_lit.node] != c);
+                }
+                let new_n_id = lit(&mut egraph, &index_map, new_lit);
                 if new_n_id != c {
                     //let c_new_n = egraph.add(Prop::Connect(new_n_id));
-                    egraph.union(c, new_n_id);
+                    egraph.union_trusted(c, new_n_id, format!("trace: {}", insn));
                     //index_map.insert(new_n, c);
                 }
             }
-            _ => {
-                panic!("invalid op in insn: {}", op);
-            }
         }
-        ind += 1;
     }
+    egraph.analysis.cause = None;
+    exit_on_contradiction(&egraph);
     // re-canonicalize e-graph
     let egraph_c = egraph.clone(); 
     egraph.classes_mut().for_each(|c| {
@@ -195,13 +160,16 @@ fn egraph_from_seqn_trace(
         *po = egraph.find(*po);
     }
     let out_net_to_eclass: IndexMap<String, Id> = outnodes.split(" ").into_iter().enumerate().map(|(po_ind, po_net)| (po_net.to_string(), pos[po_ind])).collect();
-    let concat_node = egraph.add(Prop::Concat(pos));
+    let originals: IndexMap<String, Id> = outnodes.split(" ").zip(originals).map(|(po_net, id)| (po_net.to_string(), id)).collect();
     EqsatOptimizer {
         egraph,
         rules: Vec::new(),
-        concat_node,
         out_net_to_eclass,
+        originals,
         params: OptimizerParams::default(),
+        cost_model,
+        incumbent: None,
+        rule_log: None,
         stats: OptimizerStats::default().with_egraph_stats(&egraph_c)
     }
 }
@@ -210,8 +178,11 @@ fn egraph_from_seqn(
     innodes: &str,
     outnodes: &str,
     eqns: &str,
+    public: &HashSet<String>,
+    cost_model: Arc<dyn HeCostModel>,
+    explain: bool,
 ) -> EqsatOptimizer {
-    let (mut egraph, mut ckt_node_to_eclass) = egraph_init_from_pis(innodes);
+    let (mut egraph, mut ckt_node_to_eclass) = egraph_init_from_pis(innodes, public, cost_model.clone(), explain);
 
     for (_, eqn) in eqns.lines().into_iter().enumerate() {
         let mut split = eqn.split("=");
@@ -222,54 +193,51 @@ fn egraph_from_seqn(
         let src1_s = rhs.next().unwrap();
         if let Ok(l1) = src1_s.parse::<u32>() {
             if ckt_node_to_eclass.get(src1_s).is_none() {
-                ckt_node_to_eclass.insert(src1_s.to_string(), egraph.add(Prop::Int(l1)));
+                ckt_node_to_eclass.insert(src1_s.to_string(), egraph.add_uncanonical(Prop::Int(l1)));
             }
         }
 
         let src2_s = rhs.next().unwrap();
         if let Ok(l2) = src2_s.parse::<u32>() {
             if ckt_node_to_eclass.get(src2_s).is_none() {
-                ckt_node_to_eclass.insert(src2_s.to_string(), egraph.add(Prop::Int(l2)));
+                ckt_node_to_eclass.insert(src2_s.to_string(), egraph.add_uncanonical(Prop::Int(l2)));
             }
         }
 
         let src1 = ckt_node_to_eclass.get(src1_s);
         let src2 = ckt_node_to_eclass.get(src2_s);
         let id = match op {
-            "^" => egraph.add(Prop::Xor([
+            "^" => egraph.add_uncanonical(Prop::Xor([
                 src1.unwrap().to_owned(),
                 src2.unwrap().to_owned(),
             ])),
-            "*" => egraph.add(Prop::And([
+            "*" => egraph.add_uncanonical(Prop::And([
                 src1.unwrap().to_owned(),
                 src2.unwrap().to_owned(),
             ])),
-            "!" => egraph.add(Prop::Not(src1.unwrap().to_owned())),
+            "!" => egraph.add_uncanonical(Prop::Not(src1.unwrap().to_owned())),
             "w" => src1.unwrap().to_owned(),
             _ => panic!("unrecognized op {}", op),
         };
         ckt_node_to_eclass.insert(lhs.to_string(), id);
     }
     let mut out_net_to_eclass: IndexMap<String, Id> = IndexMap::new();
-    let mut outnodes_vec: Vec<Id> = Vec::new();
+    let mut originals: IndexMap<String, Id> = IndexMap::new();
     for outnode in outnodes.split(" ") {
         let outnode_id = egraph.find(*ckt_node_to_eclass.get(outnode).unwrap());
-        outnodes_vec.push(outnode_id);
         out_net_to_eclass.insert(outnode.to_string(),outnode_id);
+        originals.insert(outnode.to_string(), ckt_node_to_eclass[outnode]);
     }
 
-    /*egraph.add(Prop::Concat2([outnodes_vec[0], outnodes_vec[1]]));
-    for n in &outnodes_vec[2..] {
-        concat_node = egraph.add(Prop::Concat2([concat_node, *n]));
-    }*/
-
-    //let concat_node = egraph.add(Prop::Concat(outnodes_vec));
     EqsatOptimizer {
         egraph,
-        concat_node: egg::Id::from(0 as usize),
         rules: Vec::new(),
         out_net_to_eclass,
+        originals,
         params: OptimizerParams::default(),
+        cost_model,
+        incumbent: None,
+        rule_log: None,
         stats: OptimizerStats::default()
     }
 }
@@ -285,8 +253,25 @@ struct OptimizerParams {
     ilp_time_limit: f64,
     comm_matching: bool,
     strict_deadlines: bool,
+    /// End of the total time budget, shared by saturation and extraction
+    deadline: Option<Instant>,
+    /// Seed for the randomized extractors
+    seed: u64,
+    /// Order equations to minimize the peak number of live ciphertexts
+    memory_order: bool,
+    /// Merge classes proven equivalent by simulation and exact checks between iterations
+    fraig: bool,
+    /// Record per-rule matches, applications and created e-nodes while saturating
+    rule_stats: bool,
+    scheduler: scheduler::SchedulerKind,
+    /// Default match limit and ban length of the backoff schedulers
+    match_limit: usize,
+    ban_length: usize,
 }
 
+/// With the md-priority scheduler, matches off the critical path are applied every this many iterations
+const MD_PRIORITY_PERIOD: usize = 4;
+
 
 struct OptimizerStats {
     final_eclasses: usize,
@@ -307,13 +292,13 @@ impl Default for OptimizerStats {
 }
 
 impl OptimizerStats {
-    fn with_egraph_stats(mut self, egraph: &EGraph<Prop, ()>) -> Self {
+    fn with_egraph_stats(mut self, egraph: &EGraph<Prop, PropAnalysis>) -> Self {
         self.final_eclasses = egraph.number_of_classes();
         self.final_enodes = egraph.total_number_of_nodes();
         self
     }
 
-    fn set_egraph_stats(&mut self, egraph: &EGraph<Prop, ()>) {
+    fn set_egraph_stats(&mut self, egraph: &EGraph<Prop, PropAnalysis>) {
         self.final_eclasses = egraph.number_of_classes();
         self.final_enodes = egraph.total_number_of_nodes();
     }
@@ -328,14 +313,40 @@ impl OptimizerStats {
 }
 
 struct EqsatOptimizer {
-    egraph: EGraph<Prop, ()>,
-    rules: Vec<Rewrite<Prop, ()>>,
-    concat_node: Id,
+    egraph: EGraph<Prop, PropAnalysis>,
+    rules: Vec<rules::Rule>,
     out_net_to_eclass: IndexMap<String, Id>,
+    /// Output -> uncanonical id of the output's e-node in the input network
+    originals: IndexMap<String, Id>,
     params: OptimizerParams,
+    cost_model: Arc<dyn HeCostModel>,
+    incumbent: Option<Arc<anytime::Incumbent>>,
+    /// Rule statistics of the last saturation, if `params.rule_stats`
+    rule_log: Option<rule_stats::RuleLog>,
     stats: OptimizerStats 
 }
 
+fn with_scheduler(
+    runner: Runner<Prop, PropAnalysis>,
+    scheduler: impl RewriteScheduler<Prop, PropAnalysis> + 'static,
+    rules: &[rules::Rule],
+    log: &Option<Rc<RefCell<rule_stats::RuleLog>>>,
+) -> Runner<Prop, PropAnalysis> {
+    let scheduler = scheduler::Blaming(scheduler::Prioritized::new(scheduler, rules));
+    match log {
+        Some(log) => runner.with_scheduler(rule_stats::Recording::new(scheduler, log.clone())),
+        None => runner.with_scheduler(scheduler),
+    }
+}
+
+/// Reports an unsound rule or trace step that merged true with false, and exits.
+fn exit_on_contradiction(egraph: &EGraph<Prop, PropAnalysis>) {
+    if let Some(contradiction) = &egraph.analysis.contradiction {
+        eprintln!("{}", contradiction);
+        std::process::exit(1);
+    }
+}
+
 fn find_cycles<L, N>(egraph: &EGraph<L, N>, mut f: impl FnMut(Id, usize))
 where
     L: Language,
@@ -375,7 +386,7 @@ where
 }
 
 impl EqsatOptimizer {
-    fn with_rules(mut self, rules: Vec<Rewrite<Prop, ()>>) -> Self {
+    fn with_rules(mut self, rules: Vec<rules::Rule>) -> Self {
         self.rules = rules;
         self
     }
@@ -385,18 +396,68 @@ impl EqsatOptimizer {
         self
     }
 
+    fn with_incumbent(mut self, incumbent: Arc<anytime::Incumbent>) -> Self {
+        self.incumbent = Some(incumbent);
+        self
+    }
+
+    fn network_writer(&self) -> extraction_ser::NetworkWriter {
+        if self.params.memory_order {
+            liveness::min_peak_writer
+        } else {
+            extraction_ser::dag_network_writer
+        }
+    }
+
+    /// Hands an extracted network to the anytime incumbent, if any.
+    fn offer(&self, cost: &NetworkCost, ntk: &str) {
+        if let Some(incumbent) = &self.incumbent {
+            incumbent.offer(cost.scalar(self.cost_model.as_ref()), ntk);
+        }
+    }
+
+    /// Required levels of the outputs that have one, by output class
+    /// (the tightest one where outputs share a class).
+    fn required_levels(&self) -> HashMap<egraph_serialize::ClassId, usize> {
+        let mut required: HashMap<egraph_serialize::ClassId, usize> = HashMap::new();
+        for (o_name, o_id) in self.out_net_to_eclass.iter() {
+            if let Some(level) = self.cost_model.required_level(o_name) {
+                let class = egraph_serialize::ClassId::new(Into::<u32>::into(*o_id));
+                required.entry(class).and_modify(|l| *l = (*l).min(level)).or_insert(level);
+            }
+        }
+        required
+    }
+
     fn saturate_egg (
         &mut self,
     ) {
         let start_time = Instant::now();
         dbg!(&self.params);
+        let time_limit = match self.params.deadline {
+            // saturation gets half of what is left of the budget, extraction the rest
+            Some(deadline) => Duration::from_secs(self.params.time_limit).min(deadline.saturating_duration_since(Instant::now()) / 2),
+            None => Duration::from_secs(self.params.time_limit),
+        };
         let runner = Runner::default()
             .with_egraph(self.egraph.clone())
-            .with_time_limit(Duration::from_secs(self.params.time_limit))
+            // everything after a contradiction is meaningless
+            .with_hook(|runner| runner.egraph.analysis.contradiction.clone().map_or(Ok(()), Err))
+            .with_time_limit(time_limit)
             .with_node_limit(self.params.node_limit)
             .with_iter_limit(self.params.iter_limit);
-        //.with_scheduler(BackoffScheduler::default().with_initial_match_limit(100))
 
+        // sweep before every iteration, so rules also match on the merged classes
+        let fraig_merges = Rc::new(Cell::new(0));
+        let runner = if self.params.fraig {
+            let merges = fraig_merges.clone();
+            runner.with_hook(move |runner| {
+                merges.set(merges.get() + sweep::fraig_sweep(&mut runner.egraph));
+                Ok(())
+            })
+        } else {
+            runner
+        };
         let runner = if self.params.comm_matching {
             runner
         } else {
@@ -408,14 +469,32 @@ impl EqsatOptimizer {
             runner
         };
 
+        let log = self.params.rule_stats.then(|| Rc::new(RefCell::new(rule_stats::RuleLog::new(&self.egraph))));
+        let backoff = scheduler::backoff(&self.rules, self.params.match_limit, self.params.ban_length);
+        let runner = match self.params.scheduler {
+            scheduler::SchedulerKind::Simple => with_scheduler(runner, SimpleScheduler, &self.rules, &log),
+            scheduler::SchedulerKind::Backoff => with_scheduler(runner, backoff, &self.rules, &log),
+            scheduler::SchedulerKind::MdPriority => {
+                let roots = self.out_net_to_eclass.values().cloned().collect();
+                with_scheduler(runner, scheduler::MdPriority::new(backoff, roots, MD_PRIORITY_PERIOD), &self.rules, &log)
+            }
+        };
+
         dbg!(self.rules.len());
-        let runner = runner.run(self.rules.iter());
+        let mut runner = runner.run(self.rules.iter().map(|r| &r.rewrite));
+        exit_on_contradiction(&runner.egraph);
+        if let Some(log) = &log {
+            log.borrow_mut().set_iterations(&runner.iterations);
+        }
+        if self.params.fraig {
+            fraig_merges.set(fraig_merges.get() + sweep::fraig_sweep(&mut runner.egraph));
+            println!("fraig sweep: {} merges", fraig_merges.get());
+        }
 
-        // Remap output net IDs.
+        // Remap output net IDs. Outputs may now share a class.
         for (_, id) in self.out_net_to_eclass.iter_mut() {
             *id = runner.egraph.find(*id);
         }
-        self.concat_node = runner.egraph.find(self.concat_node);
 
         // Create mapping from new -> old based on saturation
         // PRECONDITION: new_egraph must have been created or cloned from old_egraph initially (otherwise find is meaningless)
@@ -431,30 +510,83 @@ impl EqsatOptimizer {
         self.stats.set_egraph_stats(&runner.egraph);
         self.stats.set_saturation_time(sat_time);
         self.egraph = runner.egraph;
+        self.rule_log = log.map(|log| log.take());
+    }
+
+    /// Writes the rule statistics of the last saturation next to `outfile`, attributing the
+    /// equations of `network` to the rules that added their e-nodes.
+    fn write_rule_stats(&self, outfile: &Path, network: &str, innodes: &str, outnodes: &str) {
+        let Some(log) = &self.rule_log else { return };
+        let origins = log.network_origins(&self.egraph, network, innodes, outnodes);
+        for (origin, n) in &origins {
+            println!("network nodes from {}: {}", origin, n);
+        }
+        let names: Vec<Symbol> = self.rules.iter().map(|r| r.rewrite.name).collect();
+        log.write(outfile, &names, &origins);
+    }
+
+    /// Writes the level-by-level batch schedule of `network` next to `outfile` when networks are
+    /// scored per batch.
+    fn write_schedule(&self, outfile: &Path, network: &str, innodes: &str, outnodes: &str) {
+        let model = self.cost_model.as_ref();
+        if model.simd_slots().is_none() {
+            return;
+        }
+        let depth = |node: &Prop| self.egraph.analysis.levels(node, |c| self.egraph[c].data.plain);
+        schedule::write_schedule(network, innodes, outnodes, &depth, outfile);
+    }
+
+    /// Writes explanations of how each output of `network` was derived from the input network,
+    /// if the e-graph was built with explanations enabled.
+    fn write_explanations(&mut self, outfile: &Path, network: &str, innodes: &str, outnodes: &str) {
+        if self.egraph.are_explanations_enabled() {
+            explain::write_explanations(&mut self.egraph, &self.originals, network, innodes, outnodes, outfile);
+        }
     }
 
-    fn mc_ilp_extract(&mut self, depth_bound: Option<usize>) -> Option<(u64, u64, String)> {
+    /// MC-minimal ILP extraction, once per depth bound (`None` = unbounded).
+    /// The ILP is built once and warm-started from the greedy DAG extraction, so every
+    /// bound the heuristic meets gets a result at least as good as the heuristic's.
+    fn mc_ilp_extract(&mut self, depth_bounds: &[Option<usize>]) -> Vec<Option<(NetworkCost, String)>> {
         let start_time = Instant::now();
 
         // extraction
-        let mut extractor = LpExtractor::new(&self.egraph, extraction_unser::MultComplexity, &[self.concat_node], depth_bound);
-        extractor.timeout(self.params.ilp_time_limit); // way too much time
-        
-        let Some((exp, _)) = extractor.solve_multiple(&[self.concat_node]) else { return None };
-        let mc = exp.iter().filter(|p| match p {
-            Prop::And(_) => true,
-            _ => false
-        }).count() as u64;
+        let model = self.cost_model.as_ref();
+        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter(), model);
+        let roots = egraph_ser.root_eclasses.clone();
+        let start = global_greedy_dag::mc_extract(&egraph_ser, &roots, HashMap::new(), model);
+        let max_bound = if depth_bounds.contains(&None) { None } else { depth_bounds.iter().flatten().max().copied() };
+        let mut extractor = ilp_extract::IlpExtractor::new(&egraph_ser, &roots, model, &start, max_bound, self.params.ilp_time_limit);
+        extractor.set_required(&self.required_levels());
+
+        let results = depth_bounds.iter().enumerate().map(|(i, depth_bound)| {
+            if let Some(deadline) = self.params.deadline {
+                // the remaining bounds share what is left of the budget
+                let left = deadline.saturating_duration_since(Instant::now()).as_secs_f64();
+                extractor.set_time_limit(self.params.ilp_time_limit.min(left / (depth_bounds.len() - i) as f64));
+            }
+            let mut cost_analysis = extractor.solve(*depth_bound)?;
+            let (cost, ntk) = (self.network_writer())(&egraph_ser, &mut cost_analysis, &self.out_net_to_eclass, model);
+            self.offer(&cost, &ntk);
+            Some((cost, ntk))
+        }).collect();
 
         let extract_time = Instant::now() - start_time;
         self.stats.set_extraction_time(extract_time);
-        let (md, ntk) = extraction_unser::recexpr_traversal(exp, &self.out_net_to_eclass);
-        Some((md, mc, ntk))
+        results
     }
-
-    fn mc_md_dag(&mut self) -> (u64, u64, String) {
+    fn mc_md_dag(&mut self) -> (NetworkCost, String) {
         let start_time = Instant::now();
-        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter());
+        let (cost, ntk) = self.dag_extract();
+        let extract_time = Instant::now() - start_time;
+        self.stats.set_extraction_time(extract_time);
+        (cost, ntk)
+    }
+
+    /// Greedy DAG extraction, handed to the incumbent; does not touch the stats.
+    fn dag_extract(&self) -> (NetworkCost, String) {
+        let model = self.cost_model.as_ref();
+        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter(), model);
         //let mut cycles: HashMap<NodeId, usize> = HashMap::new();
         //find_cycles(&self.egraph, |id, i| {
         //    let id: usize = id.into();
@@ -464,10 +596,211 @@ impl EqsatOptimizer {
         //});
         //extraction_ser::ser_egraph_to_dot::<&str>(&egraph_ser, &HashMap::new(), &cycles, "out.dot");
 
-        let mut cost_analysis = global_greedy_dag::mc_extract(&egraph_ser, &egraph_ser.root_eclasses, HashMap::new());
+        let mut cost_analysis = global_greedy_dag::mc_extract(&egraph_ser, &egraph_ser.root_eclasses, HashMap::new(), model);
+        let (cost, ntk) = (self.network_writer())(&egraph_ser, &mut cost_analysis, &self.out_net_to_eclass, model);
+        self.offer(&cost, &ntk);
+        (cost, ntk)
+    }
+
+    /// Greedy DAG extractions ranked by the cost model itself (`None`) or by gate cost plus
+    /// the given weight per level of depth.
+    /// Greedy extractions ranking by the model, or by gate cost plus a weight per level, with
+    /// `xor_weight` added per XOR gate.
+    fn greedy_candidates(&self, egraph_ser: &egraph_serialize::EGraph, weights: &[Option<f64>], xor_weight: f64) -> Vec<(NetworkCost, extraction_ser::ExtractionResult, String)> {
+        let model = self.cost_model.as_ref();
+        let xor_weighted = XorWeighted { base: model, weight: xor_weight };
+        let ranking: &dyn HeCostModel = if xor_weight > 0.0 { &xor_weighted } else { model };
+        weights.iter().map(|weight| {
+            let mut cost_analysis = match weight {
+                None => global_greedy_dag::mc_extract(egraph_ser, &egraph_ser.root_eclasses, HashMap::new(), ranking),
+                Some(weight) => global_greedy_dag::mc_extract(egraph_ser, &egraph_ser.root_eclasses, HashMap::new(), &DepthWeighted { base: ranking, weight: *weight }),
+            };
+            let (cost, ntk) = (self.network_writer())(egraph_ser, &mut cost_analysis, &self.out_net_to_eclass, model);
+            self.offer(&cost, &ntk);
+            (cost, cost_analysis, ntk)
+        }).collect()
+    }
+
+    fn pareto_extract(&mut self, sweep: usize, ilp_iters: usize) -> Vec<(NetworkCost, String)> {
+        let start_time = Instant::now();
+        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter(), self.cost_model.as_ref());
+
+        let weights: Vec<Option<f64>> = [None, Some(0.0)].into_iter()
+            .chain((0..sweep).map(|i| Some(0.25 * 2f64.powi(i as i32))))
+            .collect();
+        // the model may not price XORs at all, so the sweep is repeated with a small and a large
+        // weight per XOR to reach the low-XOR end of the front
+        let mut points: Vec<(NetworkCost, String)> = [0.0, 0.1, 1.0].into_iter()
+            .flat_map(|xor_weight| self.greedy_candidates(&egraph_ser, &weights, xor_weight))
+            .map(|(cost, _, ntk)| (cost, ntk))
+            .collect();
+        let extract_time = Instant::now() - start_time;
+        self.stats.set_extraction_time(extract_time);
+
+        // ILP fills in the low-MC end of each depth the heuristics reached
+        let min_md = points.iter().map(|(c, _)| c.md).min().unwrap_or(0) as usize;
+        if ilp_iters > 0 {
+            let bounds: Vec<Option<usize>> = (0..ilp_iters).map(|i| Some(min_md + i)).collect();
+            points.extend(self.mc_ilp_extract(&bounds).into_iter().flatten());
+        }
+        points
+    }
+
+    /// Minimum-MC extraction whose MD is at most `md_target` (the lowest achievable MD by default)
+    /// and whose outputs meet their required levels. Returns why as the error when an output cannot
+    /// be done in time, or when only the depth-first fallback finds a network and it is late.
+    fn mc_md_target_extract(&mut self, md_target: Option<usize>) -> Result<(NetworkCost, String), String> {
+        let start_time = Instant::now();
+        let model = self.cost_model.as_ref();
+        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter(), model);
+        let roots = egraph_ser.root_eclasses.clone();
+
+        let required = self.required_levels();
+        let (all_md, min_md, bounds) = md_slack::calc_bounds(&egraph_ser, &roots, model, md_target, &required);
+        let target = md_target.unwrap_or(min_md);
+        if target < min_md {
+            return Err(format!("MD target {} is infeasible; lowest achievable MD = {}", target, min_md));
+        }
+        // level each output must be done by
+        let due: Vec<(&String, egraph_serialize::ClassId, usize)> = self.out_net_to_eclass.iter()
+            .map(|(name, id)| {
+                let class = egraph_serialize::ClassId::new(Into::<u32>::into(*id));
+                (name, class, required.get(&class).map_or(target, |r| (*r).min(target)))
+            })
+            .collect();
+        for (name, class, level) in &due {
+            let lowest = all_md.lowest_md(class);
+            if lowest > *level {
+                return Err(format!("output {} is required by level {} but needs at least {} levels", name, level, lowest));
+            }
+        }
+        let mut cost_analysis = md_mc_balanced_extract::mc_extract(&egraph_ser, &roots, &bounds, model);
+        if !roots.iter().all(|r| cost_analysis.contains_key(r)) {
+            // The bounds are only necessary conditions, so the greedy search can still reject every
+            // term of an output. Weighting each level above the total gate cost makes the greedy
+            // extractor depth-first, which always reaches the lowest MD.
+            eprintln!("warning: no bounded term for every output; falling back to depth-first extraction, which is not MC-minimal");
+            let weight = egraph_ser.nodes.values().map(|n| n.cost.into_inner()).sum::<f64>() + 1.0;
+            let depth_first = DepthWeighted { base: model, weight };
+            cost_analysis = global_greedy_dag::mc_extract(&egraph_ser, &roots, HashMap::new(), &depth_first);
+            if let Some((name, _, level)) = due.iter().find(|(_, class, level)| cost_analysis[class].0 > *level) {
+                return Err(format!("the depth-first fallback misses the required level {} of output {}", level, name));
+            }
+        }
+
+        let extract_time = Instant::now() - start_time;
+        self.stats.set_extraction_time(extract_time);
+        let (cost, ntk) = (self.network_writer())(&egraph_ser, &mut cost_analysis, &self.out_net_to_eclass, model);
+        self.offer(&cost, &ntk);
+        Ok((cost, ntk))
+    }
+
+    fn stochastic_extract(&mut self, method: stochastic::Method, iters: usize, beam_width: usize) -> (NetworkCost, String) {
+        let start_time = Instant::now();
+        let model = self.cost_model.as_ref();
+        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter(), model);
+        let roots = egraph_ser.root_eclasses.clone();
+
+        let start = global_greedy_dag::mc_extract(&egraph_ser, &roots, HashMap::new(), model);
+        let mut cost_analysis = match method {
+            stochastic::Method::Anneal => stochastic::anneal(&egraph_ser, &roots, &start, model, self.params.seed, iters),
+            stochastic::Method::Beam => stochastic::beam(&egraph_ser, &roots, &start, model, self.params.seed, beam_width),
+        };
+
+        let extract_time = Instant::now() - start_time;
+        self.stats.set_extraction_time(extract_time);
+        let (cost, ntk) = (self.network_writer())(&egraph_ser, &mut cost_analysis, &self.out_net_to_eclass, model);
+        self.offer(&cost, &ntk);
+        (cost, ntk)
+    }
+    /// Runs every extractor concurrently on one serialized e-graph and keeps the best network
+    /// under the cost model. ILP runs unbounded and at `ilp_iters` depth bounds from the greedy MD up.
+    fn portfolio_extract(&mut self, ilp_iters: usize) -> Option<(NetworkCost, String)> {
+        let start_time = Instant::now();
+        let model = self.cost_model.as_ref();
+        let egraph_ser = &serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values().into_iter(), model);
+        let roots = &egraph_ser.root_eclasses.clone();
+        let seed = self.params.seed;
+        let required = &self.required_levels();
+        let ilp_time_limit = match self.params.deadline {
+            Some(deadline) => self.params.ilp_time_limit.min(deadline.saturating_duration_since(Instant::now()).as_secs_f64()),
+            None => self.params.ilp_time_limit,
+        };
+
+        // the greedy DAG result is where the ILP and stochastic extractors start from
+        let greedy = &global_greedy_dag::mc_extract(egraph_ser, roots, HashMap::new(), model);
+        let greedy_time = Instant::now() - start_time;
+        let greedy_md = roots.iter().map(|r| greedy[r].0).max().unwrap_or(0);
+
+        let mut extractors: Vec<portfolio::Extractor> = Vec::new();
+        extractors.push(("greedy-area".to_string(), Box::new(move || {
+            Some(global_greedy_dag::mc_extract(egraph_ser, roots, HashMap::new(), &DepthWeighted { base: model, weight: 0.0 }))
+        })));
+        extractors.push(("balanced".to_string(), Box::new(move || {
+            let (_, _, bounds) = md_slack::calc_bounds(egraph_ser, roots, model, None, required);
+            let result = md_mc_balanced_extract::mc_extract(egraph_ser, roots, &bounds, model);
+            roots.iter().all(|r| result.contains_key(r)).then_some(result)
+        })));
+        extractors.push(("anneal".to_string(), Box::new(move || {
+            Some(stochastic::anneal(egraph_ser, roots, greedy, model, seed, portfolio::ANNEAL_ITERS))
+        })));
+        extractors.push(("beam".to_string(), Box::new(move || {
+            Some(stochastic::beam(egraph_ser, roots, greedy, model, seed, portfolio::BEAM_WIDTH))
+        })));
+        for i in 0..ilp_iters+1 {
+            let md_b = if i == 0 { None } else { Some(greedy_md + (i-1)) };
+            let name = md_b.map_or("ilp".to_string(), |b| format!("ilp-md{}", b));
+            extractors.push((name, Box::new(move || {
+                let mut extractor = ilp_extract::IlpExtractor::new(egraph_ser, roots, model, greedy, md_b, ilp_time_limit);
+                extractor.set_required(required);
+                extractor.solve(md_b)
+            })));
+        }
+
+        let mut entries = vec![portfolio::Entry {
+            name: "greedy-dag".to_string(),
+            time: greedy_time,
+            result: Some((self.network_writer())(egraph_ser, &mut greedy.clone(), &self.out_net_to_eclass, model)),
+        }];
+        entries.extend(portfolio::run(egraph_ser, extractors, self.network_writer(), &self.out_net_to_eclass, model));
+        for (cost, ntk) in entries.iter().filter_map(|e| e.result.as_ref()) {
+            self.offer(cost, ntk);
+        }
+        let winner = portfolio::report(&entries, model).and_then(|e| e.result.clone());
+
+        let extract_time = Instant::now() - start_time;
+        self.stats.set_extraction_time(extract_time);
+        winner
+    }
+    fn bootstrap_extract(&mut self, level_budget: usize, bootstrap_cost: f64) -> Option<(NetworkCost, bootstrap::BootstrapPlan, String)> {
+        let start_time = Instant::now();
+        let model = self.cost_model.as_ref();
+        let egraph_ser = serde::serialize_in_mem(&self.egraph, self.out_net_to_eclass.values(), model);
+
+        // The bootstrap-priced extraction comes first; the plain candidates go from the model's
+        // own depth-first ranking to pure gate count, the weights in between trading ANDs
+        // against bootstraps.
+        let mut candidates = Vec::new();
+        if let Some(mut cost_analysis) = bootstrap::extract(&egraph_ser, &egraph_ser.root_eclasses, model, level_budget, bootstrap_cost) {
+            let (cost, ntk) = (self.network_writer())(&egraph_ser, &mut cost_analysis, &self.out_net_to_eclass, model);
+            self.offer(&cost, &ntk);
+            candidates.push((cost, cost_analysis, ntk));
+        }
+        let per_level = bootstrap_cost / level_budget.max(1) as f64;
+        let weights = [None, Some(0.0), Some(per_level), Some(bootstrap_cost)];
+        candidates.extend(self.greedy_candidates(&egraph_ser, &weights, 0.0));
+        let mut best: Option<(NetworkCost, bootstrap::BootstrapPlan, String)> = None;
+        for (cost, cost_analysis, ntk) in candidates {
+            let Some(plan) = bootstrap::place_bootstraps(&egraph_ser, &cost_analysis, &cost, model, level_budget, bootstrap_cost) else { continue };
+            println!("candidate: MD = {}; MC = {}; bootstraps = {}; total = {}", cost.md, cost.mc, plan.locations.len(), plan.total_cost);
+            if best.as_ref().is_none_or(|(_, b, _)| plan.total_cost < b.total_cost) {
+                best = Some((cost, plan, ntk));
+            }
+        }
+
         let extract_time = Instant::now() - start_time;
         self.stats.set_extraction_time(extract_time);
-        extraction_ser::dag_network_writer(&egraph_ser, &mut cost_analysis, &self.out_net_to_eclass)
+        best
     }
 }
 
@@ -479,10 +812,67 @@ impl EqsatOptimizer {
 #[derive(Subcommand, PartialEq)]
 enum FlowMode {
     SatMcIlp,
-    SatMcMdDag,
+    SatMcMdDag {
+        /// Largest MD allowed for the extracted network (default: the lowest achievable MD)
+        #[arg(long)]
+        md_target: Option<usize>,
+    },
     TracingHEConverge {
         #[arg(long)]
         ilp_iters: Option<usize>,
+        /// Run all extractors in parallel and keep the best, instead of greedy then ILP in turn
+        #[arg(long, action=clap::ArgAction::SetTrue)]
+        portfolio: bool,
+    },
+    Pareto {
+        /// Number of ILP depth bounds to try, starting from the lowest MD found
+        #[arg(long)]
+        ilp_iters: Option<usize>,
+        /// Number of depth weights swept by the greedy extractor, once per XOR weight (0, 0.1 and 1)
+        #[arg(long, default_value_t = 8)]
+        sweep: usize,
+    },
+    /// Extract with bootstraps priced in (an operand is refreshed whenever a gate would exceed the
+    /// level budget), next to a few plain greedy candidates, place bootstraps on each and keep the
+    /// cheapest (a heuristic, not a joint optimum). Every refreshed net is marked with a
+    /// `# bootstrap <net>` line after its equation
+    BootstrapAware {
+        /// Number of levels available between bootstraps
+        #[arg(long)]
+        level_budget: usize,
+        /// Cost of one bootstrap, in AND gates
+        #[arg(long, default_value_t = 100.0)]
+        bootstrap_cost: f64,
+    },
+    /// Find the smallest subset of the rules that reaches the same MD and MC (greedy DAG extraction)
+    /// on the input network (replaying --trace if given) and every --bench network; the subset's
+    /// rule names are written to the output path, one per line, for use with --rules-enable.
+    /// Needs --egg-iter-limit and --egg-node-limit and no time limit, so that runs are repeatable
+    AblateRules {
+        /// More benchmark networks the subset has to do as well on
+        #[arg(long)]
+        bench: Vec<PathBuf>,
+        /// Search strategy
+        #[arg(long, value_enum, default_value_t = ablate::Method::Greedy)]
+        method: ablate::Method,
+    },
+    /// Generalize the U and O steps of the --trace into rules over cut variables, verified by
+    /// truth table; the rules are written to the output path
+    LearnRules {
+        /// Deepest cut tried below each side of a step, in gates
+        #[arg(long, default_value_t = 3)]
+        cone_depth: usize,
+    },
+    Stochastic {
+        /// Search strategy
+        #[arg(long, value_enum, default_value_t = stochastic::Method::Anneal)]
+        method: stochastic::Method,
+        /// Number of annealing moves
+        #[arg(long, default_value_t = 20000)]
+        iters: usize,
+        /// Number of assignments kept by the beam search
+        #[arg(long, default_value_t = 8)]
+        beam_width: usize,
     },
 }
 
@@ -498,9 +888,27 @@ struct Args {
     /// Rewriting rules (can specify multiple)
     #[arg(long)]
     rules: Vec<PathBuf>,
+    /// Only use rules with one of these tags (comma separated; a rule's name is also a tag)
+    #[arg(long, value_delimiter = ',')]
+    rules_enable: Vec<String>,
+    /// Leave out rules with any of these tags (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    rules_disable: Vec<String>,
     /// Trace file to construct e-graph
     #[arg(long)]
     trace: Option<PathBuf>,
+    /// Only replay the first N rewrite steps (U, and O replacing an output) of the trace
+    #[arg(long, requires = "trace", conflicts_with = "trace_range")]
+    trace_prefix: Option<usize>,
+    /// Only replay the trace's rewrite steps START..END (numbered from 0, END exclusive)
+    #[arg(long, requires = "trace", value_parser = parse_step_range)]
+    trace_range: Option<std::ops::Range<usize>>,
+    /// Timing constraints file: arrival levels of inputs and required levels of outputs
+    #[arg(long)]
+    constraints: Option<PathBuf>,
+    /// File listing the public (plaintext) inputs, whitespace separated; ANDs with a plaintext operand are free
+    #[arg(long)]
+    public_inputs: Option<PathBuf>,
     /// Timeout in seconds (per saturation iteration)
     #[arg(long)]
     egg_time_limit: Option<u64>,
@@ -512,24 +920,87 @@ struct Args {
     egg_node_limit: Option<usize>,
     /// Timeout in seconds for ILP
     ilp_time_limit: Option<f64>,
+    /// Wall-clock budget in seconds for the whole run; the best network so far is written when it runs out
+    #[arg(long)]
+    total_time_budget: Option<u64>,
+    /// Seed for the randomized extractors
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Cost of each ciphertext live at the peak, added to the network cost. Only used to rank
+    /// finished networks against each other, not during extraction
+    #[arg(long, default_value_t = 0.0)]
+    memory_weight: f64,
+    /// Order equations to minimize the peak number of live ciphertexts
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    memory_order: bool,
+    /// Gates per batched multiplication; networks are then also scored by batches per level,
+    /// and the level-by-level schedule is written to <outfile stem>.schedule
+    #[arg(long)]
+    simd_slots: Option<usize>,
+    /// Cost of each batched multiplication (with --simd-slots)
+    #[arg(long, default_value_t = 1.0)]
+    batch_weight: f64,
+    /// HE cost model used by every extractor
+    #[arg(long, value_enum, default_value_t = CostProfile::Bgv)]
+    cost_model: CostProfile,
+    /// Weight of the critical-path latency term (tfhe cost model only)
+    #[arg(long, default_value_t = 0.0)]
+    latency_weight: f64,
 
     #[arg(long, action=clap::ArgAction::SetTrue)]
     no_comm_matching: bool,
 
     #[arg(long, action=clap::ArgAction::SetTrue)]
     strict_deadlines: bool,
+
+    /// Rule scheduler for saturation
+    #[arg(long, value_enum, default_value_t = scheduler::SchedulerKind::Backoff)]
+    scheduler: scheduler::SchedulerKind,
+    /// Matches per rule and iteration before a rule is banned (backoff and md-priority schedulers)
+    #[arg(long, default_value_t = 1000)]
+    match_limit: usize,
+    /// Iterations a rule stays banned, doubled every time (backoff and md-priority schedulers)
+    #[arg(long, default_value_t = 5)]
+    ban_length: usize,
+
+    /// Before every iteration and after saturating, merge classes with matching simulation
+    /// signatures once proven equivalent
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    fraig: bool,
+
+    /// Write per-rule matches and applications per iteration (<outfile stem>.rules.csv) and
+    /// totals with the rules behind the final network's e-nodes (<outfile stem>.rules.json)
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    rule_stats: bool,
+
+    /// Write, for each output, the rewrites (and trace unions) relating the input network to the
+    /// extracted one (<outfile stem>.explain); makes saturation slower and larger
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    explain: bool,
+
+    /// Skip rewrites whose result is deeper than the class it would join
+    #[arg(long, action=clap::ArgAction::SetTrue)]
+    depth_guard: bool,
 }
 
 fn main() {
+    let start_time = Instant::now();
     env_logger::init();
 
     let args = Args::parse();
 
     // Parse rules
-    let mut rules: Vec<Rewrite<Prop, ()>> = Vec::new();
-    for rules_f in args.rules {
-        let rules_s = std::fs::read_to_string(rules_f).unwrap();
-        parse_rules(&mut rules, &rules_s);
+    let rule_options = rules::RuleOptions {
+        depth_guard: args.depth_guard,
+        enable: args.rules_enable.clone(),
+        disable: args.rules_disable.clone(),
+    };
+    let mut rules: Vec<rules::Rule> = Vec::new();
+    for rules_f in &args.rules {
+        if let Err(e) = rules::load_rules(rules_f, &rule_options, &mut rules) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 
     // Options
@@ -559,48 +1030,115 @@ fn main() {
             .and_then(|x| x.parse::<f64>().ok())
             .unwrap_or(600.)
     });
+    let deadline = args.total_time_budget
+        .or_else(|| {
+            env_vars
+                .get("EQSATOPT_TOTAL_TIME_BUDGET")
+                .and_then(|x| x.parse::<u64>().ok())
+        })
+        .map(|budget| start_time + Duration::from_secs(budget));
 
     // Parse input network
     let infile = args.infile.as_path();
-    let in_network = std::fs::read_to_string(infile).unwrap();
-    let mut start_lines = in_network.lines();
-    let innodes = start_lines.next().unwrap();
-    let outnodes = start_lines.next().unwrap();
-    let eqns = start_lines.collect::<Vec<&str>>().join("\n");
-
-    let mut opter = if let Some(trace) = args.trace {
-        let trace = std::fs::read_to_string(trace).unwrap();
-        egraph_from_seqn_trace(innodes, outnodes, &trace)
-    } else {
-        egraph_from_seqn(innodes, outnodes, &eqns)
-    }.with_rules(rules)
-    .with_params(OptimizerParams {
+    let (innodes, outnodes, eqns) = read_network(infile);
+    let (innodes, outnodes) = (innodes.as_str(), outnodes.as_str());
+    let public: HashSet<String> = match &args.public_inputs {
+        Some(path) => std::fs::read_to_string(path).unwrap().split_whitespace().map(|s| s.to_string()).collect(),
+        None => HashSet::new(),
+    };
+    if let Some(unknown) = public.iter().find(|p| !innodes.split(" ").any(|i| i == p.as_str())) {
+        eprintln!("public input {} is not a primary input", unknown);
+        std::process::exit(1);
+    }
+
+    let params = OptimizerParams {
         time_limit,
         node_limit,
         iter_limit,
         ilp_time_limit,
         comm_matching: !args.no_comm_matching,
         strict_deadlines: args.strict_deadlines,
-    });
+        deadline,
+        seed: args.seed,
+        memory_order: args.memory_order,
+        fraig: args.fraig,
+        rule_stats: args.rule_stats,
+        scheduler: args.scheduler,
+        match_limit: args.match_limit,
+        ban_length: args.ban_length,
+    };
+
+    let trace = args.trace.as_ref().map(|path| load_trace(path, &args, innodes, outnodes));
+    if let FlowMode::AblateRules { bench, method } = &args.flow {
+        // subsets are judged by single runs, so a run must not depend on how fast the machine is
+        if args.egg_iter_limit.is_none() || args.egg_node_limit.is_none() || args.egg_time_limit.is_some() || deadline.is_some() {
+            eprintln!("ablate-rules needs --egg-iter-limit and --egg-node-limit, and no --egg-time-limit or time budget");
+            std::process::exit(1);
+        }
+        let params = OptimizerParams { time_limit: u64::MAX, ..params };
+        let benchmarks: Vec<PathBuf> = std::iter::once(args.infile.clone()).chain(bench.iter().cloned()).collect();
+        ablate_rules(&args, &rules, &params, &public, &benchmarks, trace.as_ref(), *method);
+        return;
+    }
+    if let FlowMode::LearnRules { cone_depth } = &args.flow {
+        let Some(trace) = trace.as_ref() else {
+            eprintln!("learn-rules needs a --trace");
+            std::process::exit(1);
+        };
+        learn::learn_rules(trace, *cone_depth, &args.outfile);
+        return;
+    }
+
+    let cost_model: Arc<dyn HeCostModel> = build_cost_model(&args, innodes, outnodes).into();
+
+    let incumbent = anytime::Incumbent::new(args.outfile.clone(), innodes, outnodes);
+    incumbent.install_handlers(deadline);
+
+    let mut opter = if let Some(trace) = &trace {
+        egraph_from_seqn_trace(innodes, outnodes, trace, &public, cost_model, args.explain)
+    } else {
+        egraph_from_seqn(innodes, outnodes, &eqns, &public, cost_model, args.explain)
+    }.with_rules(rules)
+    .with_params(params)
+    .with_incumbent(incumbent.clone());
+
+    // Saturation can take a while, so start from the input network in case it gets cut short.
+    // This extraction is not part of the extraction time.
+    if !opter.rules.is_empty() {
+        let seed_start = Instant::now();
+        opter.dag_extract();
+        println!("incumbent from the input network in {:.2}s", seed_start.elapsed().as_secs_f64());
+    }
 
     let network = match args.flow {
         FlowMode::SatMcIlp => {
             opter.saturate_egg();
             println!("classes = {}; nodes = {}", opter.stats.final_eclasses, opter.stats.final_enodes);
-            let (heur_md, heur_mc,ntk) = opter.mc_md_dag();
-            println!("heur = ({},{})", heur_md, heur_mc);
-            let ilp_result = opter.mc_ilp_extract(None);
-            if let Some((ilp_md,ilp_mc,_)) = ilp_result {
-                println!("ilp solution = ({},{})", ilp_md, ilp_mc);
+            let (heur, ntk) = opter.mc_md_dag();
+            println!("heur = ({},{})", heur.md, heur.mc);
+            let ilp_result = opter.mc_ilp_extract(&[None]).pop().unwrap();
+            if let Some((ilp, _)) = ilp_result {
+                println!("ilp solution = ({},{})", ilp.md, ilp.mc);
             } else {
                 println!("ilp timeout");
             }
             ntk
         }
-        FlowMode::SatMcMdDag => {
-            unimplemented!()
+        FlowMode::SatMcMdDag { md_target } => {
+            opter.saturate_egg();
+            println!("classes = {}; nodes = {}", opter.stats.final_eclasses, opter.stats.final_enodes);
+            match opter.mc_md_target_extract(md_target) {
+                Ok((cost, ntk)) => {
+                    println!("md-target solution = ({},{})", cost.md, cost.mc);
+                    ntk
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
         }
-        FlowMode::TracingHEConverge { ilp_iters } => {
+        FlowMode::TracingHEConverge { ilp_iters, portfolio } => {
             println!("classes = {}; nodes = {}", opter.stats.final_eclasses, opter.stats.final_enodes);
             let mut cycle_cnt = 0;
             find_cycles(&opter.egraph, |id, i| {
@@ -617,24 +1155,75 @@ fn main() {
                     .and_then(|x| x.parse::<usize>().ok())
                     .unwrap_or(1)
             });
-            let (best_md, heur_mc, mut best_ntk) = opter.mc_md_dag();
-            let mut best_he_cost = best_md * best_md * heur_mc; 
-
-            println!("Starting ILP HE exploration with MD = {}; MC = {}", best_md, heur_mc);
-            for i in 0..ilp_iters+1 {
-                let md_b = if i == 0 { None } else { Some(best_md as usize + (i-1)) };
-                let Some((md, mc, ntk)) = opter.mc_ilp_extract(md_b) else { continue };
-                if !md_b.is_none() && Some(md as usize) > md_b {
-                    println!("WARNING: solution returned, but did not meet MD bounds - could be normal, continuing");
-                    continue;
-                }
-                let he_cost = (md * md) as u64 * mc;
-                if he_cost < best_he_cost {
-                    best_he_cost = he_cost;
-                    best_ntk = ntk;
+            if portfolio {
+                let (cost, ntk) = opter.portfolio_extract(ilp_iters).expect("no extractor found a network");
+                println!("portfolio solution = ({},{})", cost.md, cost.mc);
+                ntk
+            } else {
+                let (heur, mut best_ntk) = opter.mc_md_dag();
+                let best_md = heur.md;
+                let mut best_he_cost = heur.scalar(opter.cost_model.as_ref());
+
+                println!("Starting ILP HE exploration ({}) with MD = {}; MC = {}", opter.cost_model.name(), heur.md, heur.mc);
+                let md_bounds: Vec<Option<usize>> = (0..ilp_iters+1)
+                    .map(|i| if i == 0 { None } else { Some(best_md as usize + (i-1)) })
+                    .collect();
+                for (md_b, ilp_result) in md_bounds.iter().copied().zip(opter.mc_ilp_extract(&md_bounds)) {
+                    let Some((ilp, ntk)) = ilp_result else { continue };
+                    if md_b.is_some() && Some(ilp.md as usize) > md_b {
+                        println!("WARNING: solution returned, but did not meet MD bounds - could be normal, continuing");
+                        continue;
+                    }
+                    let he_cost = ilp.scalar(opter.cost_model.as_ref());
+                    if he_cost < best_he_cost {
+                        best_he_cost = he_cost;
+                        best_ntk = ntk;
+                    }
                 }
+                best_ntk
             }
-            best_ntk
+        }
+        FlowMode::Pareto { ilp_iters, sweep } => {
+            if !opter.rules.is_empty() {
+                opter.saturate_egg();
+            }
+            let ilp_iters = ilp_iters.unwrap_or_else(|| {
+                env_vars
+                    .get("EQSATOPT_ILP_ITERS")
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .unwrap_or(0)
+            });
+            let front = pareto::pareto_front(opter.pareto_extract(sweep, ilp_iters));
+            for (cost, _) in &front {
+                println!("pareto point = ({},{},{})", cost.md, cost.mc, cost.xc);
+            }
+            pareto::write_front(&front, &args.outfile, innodes, outnodes);
+            let model = opter.cost_model.as_ref();
+            front
+                .into_iter()
+                .min_by(|(a, _), (b, _)| a.scalar(model).partial_cmp(&b.scalar(model)).unwrap())
+                .unwrap()
+                .1
+        }
+        FlowMode::BootstrapAware { level_budget, bootstrap_cost } => {
+            if !opter.rules.is_empty() {
+                opter.saturate_egg();
+            }
+            let Some((cost, plan, ntk)) = opter.bootstrap_extract(level_budget, bootstrap_cost) else {
+                eprintln!("no extraction fits a level budget of {}: a single gate needs more levels", level_budget);
+                std::process::exit(1);
+            };
+            println!("bootstrap-aware solution = ({},{}); bootstraps = {}; total = {}", cost.md, cost.mc, plan.locations.len(), plan.total_cost);
+            plan.annotate(&ntk)
+        }
+        FlowMode::AblateRules { .. } | FlowMode::LearnRules { .. } => unreachable!(),
+        FlowMode::Stochastic { method, iters, beam_width } => {
+            if !opter.rules.is_empty() {
+                opter.saturate_egg();
+            }
+            let (cost, ntk) = opter.stochastic_extract(method, iters, beam_width);
+            println!("{:?} solution = ({},{})", method, cost.md, cost.mc);
+            ntk
         }
     };
 
@@ -646,8 +1235,126 @@ fn main() {
     //    stats.final_eclasses,
     //    stats.final_enodes
     //);
+    opter.write_rule_stats(&args.outfile, &network, innodes, outnodes);
+    opter.write_schedule(&args.outfile, &network, innodes, outnodes);
+    opter.write_explanations(&args.outfile, &network, innodes, outnodes);
+    incumbent.finish(&network);
+}
+
+fn parse_step_range(s: &str) -> Result<std::ops::Range<usize>, String> {
+    let (start, end) = s.split_once("..").ok_or_else(|| format!("expected START..END; got {}", s))?;
+    let start = start.parse().map_err(|_| format!("bad start {}", start))?;
+    let end = end.parse().map_err(|_| format!("bad end {}", end))?;
+    Ok(start..end)
+}
+
+/// Reads and validates the trace, reporting every problem found, and keeps the rewrite steps
+/// that --trace-prefix or --trace-range ask for.
+fn load_trace(path: &Path, args: &Args, innodes: &str, outnodes: &str) -> trace::Trace {
+    let trace = trace::Trace::parse(&std::fs::read_to_string(path).unwrap()).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    let errors = trace.validate(innodes.split(" ").count());
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("{}: {}", path.display(), e);
+        }
+        std::process::exit(1);
+    }
+    let trace = match (args.trace_prefix, &args.trace_range) {
+        (Some(n), _) => trace.select(0..n),
+        (None, Some(range)) => trace.select(range.clone()),
+        (None, None) => trace,
+    };
+    trace.print_stats(outnodes);
+    trace
+}
+
+/// Input nodes, output nodes and equations of a seqn network.
+fn read_network(path: &Path) -> (String, String, String) {
+    let contents = std::fs::read_to_string(path).unwrap();
+    let mut lines = contents.lines();
+    let innodes = lines.next().unwrap().to_string();
+    let outnodes = lines.next().unwrap().to_string();
+    (innodes, outnodes, lines.collect::<Vec<&str>>().join("\n"))
+}
+
+/// The `--cost-model` profile with the wrappers the other cost options ask for.
+fn build_cost_model(args: &Args, innodes: &str, outnodes: &str) -> Box<dyn HeCostModel> {
+    let cost_model = args.cost_model.model(args.latency_weight);
+    let cost_model: Box<dyn HeCostModel> = match &args.constraints {
+        Some(path) => {
+            let constraints = constraints::Constraints::parse(&std::fs::read_to_string(path).unwrap(), innodes, outnodes)
+                .unwrap_or_else(|e| {
+                    eprintln!("{}: {}", path.display(), e);
+                    std::process::exit(1);
+                });
+            Box::new(Timed { base: cost_model, arrival: constraints.arrival, required: constraints.required })
+        }
+        None => cost_model,
+    };
+    let cost_model: Box<dyn HeCostModel> = match args.simd_slots {
+        Some(slots) => Box::new(SimdBatched { base: cost_model, slots, weight: args.batch_weight }),
+        None => cost_model,
+    };
+    if args.memory_weight > 0.0 {
+        Box::new(MemoryWeighted { base: cost_model, weight: args.memory_weight })
+    } else {
+        cost_model
+    }
+}
+
+/// Minimizes `rules` so every benchmark still gets the (MD, MC) it gets with all of them,
+/// and writes the names of the remaining rules to the output path. With a `trace`, the first
+/// benchmark's e-graph is built by replaying it.
+fn ablate_rules(
+    args: &Args,
+    rules: &[rules::Rule],
+    params: &OptimizerParams,
+    public: &HashSet<String>,
+    benchmarks: &[PathBuf],
+    trace: Option<&trace::Trace>,
+    method: ablate::Method,
+) {
+    let networks: Vec<(String, String, String)> = benchmarks.iter().map(|b| read_network(b)).collect();
+    let run = |subset: &[usize]| -> Vec<(u64, u64)> {
+        networks
+            .iter()
+            .enumerate()
+            .map(|(i, (innodes, outnodes, eqns))| {
+                let public: HashSet<String> = public.iter().filter(|p| innodes.split(" ").any(|i| i == p.as_str())).cloned().collect();
+                let cost_model: Arc<dyn HeCostModel> = build_cost_model(args, innodes, outnodes).into();
+                let opter = match trace {
+                    Some(trace) if i == 0 => egraph_from_seqn_trace(innodes, outnodes, trace, &public, cost_model, false),
+                    _ => egraph_from_seqn(innodes, outnodes, eqns, &public, cost_model, false),
+                };
+                let mut opter = opter
+                    .with_rules(subset.iter().map(|i| rules[*i].clone()).collect())
+                    .with_params(params.clone());
+                opter.saturate_egg();
+                let (cost, _) = opter.mc_md_dag();
+                (cost.md, cost.mc)
+            })
+            .collect()
+    };
+
+    let baseline = run(&(0..rules.len()).collect::<Vec<usize>>());
+    for (bench, (md, mc)) in benchmarks.iter().zip(&baseline) {
+        println!("baseline {} = ({},{})", bench.display(), md, mc);
+    }
+    let essential = ablate::minimize(rules.len(), method, |subset| {
+        run(subset).iter().zip(&baseline).all(|((md, mc), (base_md, base_mc))| md <= base_md && mc <= base_mc)
+    });
+
+    let names: Vec<&str> = essential.iter().map(|i| rules[*i].rewrite.name.as_str()).collect();
+    println!("essential rules ({} of {}): {}", names.len(), rules.len(), names.join(","));
+    std::fs::write(&args.outfile, names.iter().map(|n| format!("{}\n", n)).collect::<String>()).unwrap();
+}
+
+fn write_network(path: &Path, innodes: &str, outnodes: &str, network: &str) {
     std::fs::write(
-        args.outfile,
+        path,
         format!(
             "INORDER = {};\nOUTORDER = {};\n{}",
             innodes, outnodes, network
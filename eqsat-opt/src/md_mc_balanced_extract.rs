@@ -2,6 +2,7 @@ use std::ops::Index;
 use std::{iter, usize::MAX};
 use std::collections::{HashMap, HashSet};
     use crate::extraction_ser::ExtractionResult;
+    use crate::cost_model::HeCostModel;
 
     use indexmap::IndexMap;
 use ordered_float::{Float, NotNan};
@@ -16,7 +17,7 @@ type TermId = usize;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 struct Term {
-    //op: String,
+    op: String,
     children: Vec<TermId>,
 }
 
@@ -56,12 +57,13 @@ impl TermDag {
         &mut self,
         node_id: NodeId,
         node: &Node,
+        node_depth: usize,
         children: Vec<TermId>,
         target: Cost,
         bounds: &HashMap<ClassId, i32>
     ) -> Option<TermId> {
         let term = Term {
-            //op: node.op.clone(),
+            op: node.op.clone(),
             children: children.clone(),
         };
 
@@ -79,7 +81,7 @@ impl TermDag {
                 eclass: node.eclass.clone(),
                 node_cost,
                 total_cost: node_cost,
-                total_depth: (node.cost.round()) as usize,
+                total_depth: node_depth,
                 reachable: iter::once(node.eclass.clone()).collect(),
                 size: 1,
             });
@@ -98,17 +100,18 @@ impl TermDag {
             let biggest_child = (0..children.len())
                 .max_by_key(|i| self.info[children[*i]].size)
                 .unwrap();
-            let deepest_child = (0..children.len())
-                .max_by_key(|i| self.info[children[*i]].total_depth)
+            let deepest_child = children.iter()
+                .map(|c| self.info[*c].total_depth)
+                .max()
                 .unwrap();
-            let local_cost = (node.cost.round()) as usize;
+            let local_cost = node_depth;
 
-        
-            if (deepest_child + local_cost) as i32 > *bounds.get(&node.eclass).unwrap_or(&0) {
+            // classes without a bound never constrain the outputs
+            if (deepest_child + local_cost) as i32 > *bounds.get(&node.eclass).unwrap_or(&i32::MAX) {
                 return None;
             }
 
-            let node_cost_u= node_cost.round() as usize;
+            let node_cost_u= node_depth;
             let mut cost = node_cost + self.total_cost(children[biggest_child]);
             let mut reachable = self.info[children[biggest_child]].reachable.clone();
             let next_id = self.nodes.len();
@@ -175,7 +178,7 @@ impl TermDag {
     }
 }
 
-pub fn mc_extract<'a>(egraph: &EGraph, _roots: &[ClassId], bounds: &HashMap<ClassId, i32>) -> ExtractionResult {
+pub fn mc_extract<'a>(egraph: &EGraph, _roots: &[ClassId], bounds: &HashMap<ClassId, i32>, model: &dyn HeCostModel) -> ExtractionResult {
     let mut keep_going = true;
 
     let nodes = egraph.nodes.clone();
@@ -202,7 +205,7 @@ pub fn mc_extract<'a>(egraph: &EGraph, _roots: &[ClassId], bounds: &HashMap<Clas
                 .map(|id| termdag.total_cost(*id))
                 .unwrap_or(INFINITY);
 
-            if let Some(candidate) = termdag.make(node_id.clone(), node, children, old_cost, bounds) {
+            if let Some(candidate) = termdag.make(*node_id, node, model.node_depth(egraph, node), children, old_cost, bounds) {
                 let cadidate_cost = termdag.total_cost(candidate);
 
                 if cadidate_cost < old_cost {
@@ -218,7 +221,6 @@ pub fn mc_extract<'a>(egraph: &EGraph, _roots: &[ClassId], bounds: &HashMap<Clas
         let info = &termdag.info[term];
         result.insert(class, (info.total_depth, info.node));
     }
-    dbg!(&result);
     result
 }
 
@@ -1,11 +1,10 @@
 use std::{
-    collections::{HashMap, HashSet}, hash::Hash, io::Empty, sync::{Arc, Mutex}, usize::MAX
+    collections::{HashMap, HashSet}, hash::Hash, io::Empty, usize::MAX
 };
 
 use egraph_serialize::{ClassId, EGraph, NodeId};
-use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 
-use crate::{extraction_ser::ser_egraph_to_dot, traverse::{self, should_visit_complete_class}};
+use crate::{cost_model::HeCostModel, extraction_ser::ser_egraph_to_dot, traverse::{self, should_visit_complete_class}};
 use rayon::prelude::*;
 
 trait TraverseData<T: Clone = Self>: Clone {
@@ -98,29 +97,37 @@ enum SlackCost {
     Filtered,
 }
 
-pub struct SlackNaive {
+pub struct SlackNaive<'m> {
     pub md_lookup: HashMap<ClassId, SlackCost>,
     pub base_class: Option<ClassId>,
-    pub visited: HashSet<ClassId>
+    pub visited: HashSet<ClassId>,
+    pub model: &'m dyn HeCostModel
 }
 
-impl SlackNaive {
-    pub fn new(base_class: ClassId) -> Self {
+impl<'m> SlackNaive<'m> {
+    pub fn new(base_class: ClassId, model: &'m dyn HeCostModel) -> Self {
         Self {
             md_lookup: HashMap::new(),
             base_class: Some(base_class),
-            visited: HashSet::new()
+            visited: HashSet::new(),
+            model
         }
     }
 
-    pub fn new_all_ckt() -> Self {
+    pub fn new_all_ckt(model: &'m dyn HeCostModel) -> Self {
         Self {
             md_lookup: HashMap::new(),
             base_class: None,
-            visited: HashSet::new()
+            visited: HashSet::new(),
+            model
         }
     }
 
+    /// Lowest MD of `class` found by the traversal.
+    pub fn lowest_md(&self, class: &ClassId) -> usize {
+        self.md_lookup.get(class).unwrap_or(&SlackCost::Unvisited).unwrap_visited()
+    }
+
     /*
     fn compute_md(&mut self, egraph: &EGraph, class: egraph_serialize::ClassId) {
         self.visited.insert(class);
@@ -225,7 +232,7 @@ impl SlackCost {
     }
 }
 
-impl traverse::EGraphVisitor for SlackNaive {
+impl<'m> traverse::EGraphVisitor for SlackNaive<'m> {
     fn visit(&mut self, egraph: &EGraph, class: &egraph_serialize::Class) -> bool {
         if Some(class.id) == self.base_class {
             if !self.md_lookup.contains_key(&class.id) {
@@ -253,7 +260,7 @@ impl traverse::EGraphVisitor for SlackNaive {
             }
 
             non_unvisited_encountered = non_unvisited_encountered || (node_md != SlackCost::Unvisited); 
-            node_md = node_md.add_cost((&egraph[node]).cost.round() as usize);
+            node_md = node_md.add_cost(self.model.node_depth(egraph, &egraph[node]));
             new_worst_md = new_worst_md.min_by_node_in_class(&node_md);
         }
         if new_worst_md == SlackCost::Unvisited && non_unvisited_encountered {
@@ -550,24 +557,26 @@ impl EGraphTraversalResponder<ParentEnode> for MdBounds {
     fn handle_root(&mut self, _: ClassId) {}
 }
 
-pub fn calc_bounds(
+/// Per-class depth bounds: each class gets the levels left before the roots it reaches are due,
+/// `required[root]` where given and `md_target` elsewhere. `md_target` defaults to the lowest
+/// achievable MD, which is returned alongside.
+pub fn calc_bounds<'m>(
     egraph: &EGraph,
     _roots: &[ClassId],
-) -> (SlackNaive, usize, HashMap<ClassId, i32>) {
+    model: &'m dyn HeCostModel,
+    md_target: Option<usize>,
+    required: &HashMap<ClassId, usize>,
+) -> (SlackNaive<'m>, usize, HashMap<ClassId, i32>) {
     // should not be in the e-graph!
-    let mut all_md = SlackNaive::new_all_ckt();
+    let mut all_md = SlackNaive::new_all_ckt(model);
     traverse::egraph_pass_traverse(&egraph, &mut all_md);
-    dbg!(all_md.visited.len());
-    dbg!(egraph.classes().len());
     let ckt_md = _roots
         .iter()
-        .map(|r| all_md.md_lookup.get(r).unwrap_or(&SlackCost::Unvisited).unwrap_visited() )
+        .map(|r| all_md.lowest_md(r))
         .max()
         .unwrap();
-    dbg!(ckt_md);
 
     let mut bounds = HashMap::new();
-    let pb = ProgressBar::new(egraph.classes().len() as u64);
     /*pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} {msg}")
     .unwrap()
     .progress_chars("#>-"));
@@ -594,64 +603,38 @@ pub fn calc_bounds(
         }
         drop(slack);
     }*/
-    // Create the progress bar
-    let pb = ProgressBar::new(egraph.classes().len() as u64);
-    //pb.set_style(ProgressStyle::default_bar()
-    //    .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
-    //    .unwrap()
-    //    .progress_chars("#>-"));
-    pb.set_style(ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} {msg}")
-    .unwrap()
-    .progress_chars("#>-"));
-
-
-    // Wrap the progress bar in an Arc<Mutex> for thread-safe sharing
-    let pb = Arc::new(Mutex::new(pb));
 
+    let target = md_target.unwrap_or(ckt_md);
     let classes: Vec<(&ClassId, &egraph_serialize::Class)> = egraph.classes().iter().collect();
     let results: Vec<_> = classes
         .par_iter()
         .map(|(cid, c)| {
-            if (c.nodes.len() <= 1) { return None }
-            let mut slack = SlackNaive::new(**cid);
-            let iters: usize = traverse::egraph_pass_traverse(&egraph, &mut slack);
-            
-            // Update progress bar safely
-            {
-                let pb = pb.lock().unwrap();
-                pb.inc(1);
-                pb.set_message(format!("iters={}", iters));
-            }
-            
-            let mut worst_remaining: Option<usize> = None;
+            // single-node classes have no choice to make, but outputs always need a bound
+            if c.nodes.len() <= 1 && !_roots.contains(*cid) { return None }
+            let mut slack = SlackNaive::new(**cid, model);
+            traverse::egraph_pass_traverse(&egraph, &mut slack);
+
+            let mut tightest: Option<i32> = None;
             for root in _roots {
-                worst_remaining = slack.md_lookup.get(root).map_or(worst_remaining, |rmd| {
-                    match rmd {
-                        SlackCost::Visited(rmd2) => Some(worst_remaining.map_or(*rmd2, |wrm: usize| wrm.max(*rmd2))),
-                        _ => worst_remaining    
-                    }
-                });
+                if let Some(SlackCost::Visited(rmd)) = slack.md_lookup.get(root) {
+                    let due = *required.get(root).unwrap_or(&target) as i32;
+                    let bound = due - *rmd as i32;
+                    tightest = Some(tightest.map_or(bound, |t| t.min(bound)));
+                }
             }
 
-            if worst_remaining.is_none() {
-                println!("eclass w/ no path to root: {}", cid);
-            }
-            
-            let result = worst_remaining.map(|wr| (**cid, wr));
+            // classes that reach no root get no bound
+            let result = tightest.map(|b| (**cid, b));
             drop(slack);
             result
         })
         .filter_map(|x| x)
         .collect();
 
-    // Finish the progress bar
-    pb.lock().unwrap().finish();
-
     // Insert results into the HashMap
-    for (cid, worst_remaining) in results {
-        bounds.insert(cid, (ckt_md as i32) - (worst_remaining as i32));
+    for (cid, bound) in results {
+        bounds.insert(cid, bound);
     }
-    dbg!("b");
     (all_md, ckt_md, bounds)
 }
 
@@ -665,7 +648,7 @@ enum PruneMark {
 
 struct PruneFirstMarkTraverse<'a> {
     pruned: HashMap<ClassId, PruneMark>,
-    slack: &'a SlackNaive,
+    slack: &'a SlackNaive<'a>,
     bounds: &'a HashMap<ClassId, i32>,
 //    reachable_memo: HashMap<(ClassId, ClassId), bool>
 }
@@ -711,7 +694,7 @@ impl<'a> traverse::EGraphVisitor for PruneFirstMarkTraverse<'a> {
                                 _ => {}
                             }
                         }
-                        let cost = (&egraph[node]).cost.round() as usize;
+                        let cost = self.slack.model.node_depth(egraph, &egraph[node]);
                         if !are_children_pruned && (cost + md_child) as i32 <= *bound {
                             // as far as we know this node is not getting touched
                             // so the class shouldn't either.
@@ -801,7 +784,7 @@ impl <'a> traverse::EGraphVisitor for PruneReconstructTraverse<'a> {
 pub fn egraph_prune(
     egraph: &EGraph,
     _roots: &[ClassId],
-    slack: &SlackNaive,
+    slack: &SlackNaive<'_>,
     bounds: &HashMap<ClassId, i32>,
 ) -> (HashMap::<egg::Id,egg::Id>, egg::EGraph<crate::Prop, ()>) {
     let mut pmt = PruneFirstMarkTraverse {
@@ -919,6 +902,7 @@ mod tests {
     use egraph_serialize::{ClassId, NodeId};
 
     use crate::{
+        cost_model::LeveledBgv,
         extraction_ser::{ser_egraph_from_file, ser_egraph_to_dot},
         md_slack::{egraph_traverse, MdBounds, SlackNaive},
         *,
@@ -968,7 +952,7 @@ mod tests {
         let out_eclasses = vec![ClassId::new(7963)];
         
         for i in 0..8 {
-            let mut naive = SlackNaive::new(ClassId::new(i));
+            let mut naive = SlackNaive::new(ClassId::new(i), &LeveledBgv);
             traverse::egraph_pass_traverse(&egraph, &mut naive);
             //dbg!(naive.md_lookup);
             //dbg!(naive.worst_md);
@@ -1009,7 +993,7 @@ mod tests {
         //let out_eclasses = vec![ClassId::new(7963)];
         //let out_eclasses = vec![ClassId::new(390)];
 
-        let (slack, ckt_md, bounds) = calc_bounds(&egraph, &out_eclasses);
+        let (slack, ckt_md, bounds) = calc_bounds(&egraph, &out_eclasses, &LeveledBgv, None, &HashMap::new());
         let (ser_to_unser,pruned) = egraph_prune(&egraph, &out_eclasses, &slack, &bounds);
         pruned.dot().to_dot("egraph_pruned.dot").unwrap();
         //let annot: HashMap<NodeId, &str> = egraph.classes().iter()
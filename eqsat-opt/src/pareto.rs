@@ -0,0 +1,77 @@
+// Pareto front of (MD, MC, XOR count) trade-offs found in one e-graph.
+use std::path::{Path, PathBuf};
+
+use crate::cost_model::NetworkCost;
+
+/// `a` is at least as good as `b` everywhere and strictly better somewhere.
+pub fn dominates(a: &NetworkCost, b: &NetworkCost) -> bool {
+    a.md <= b.md && a.mc <= b.mc && a.xc <= b.xc && (a.md < b.md || a.mc < b.mc || a.xc < b.xc)
+}
+
+/// Keeps the non-dominated networks (one per distinct point), sorted by MD then MC.
+pub fn pareto_front(points: Vec<(NetworkCost, String)>) -> Vec<(NetworkCost, String)> {
+    let mut front: Vec<(NetworkCost, String)> = Vec::new();
+    for (cost, ntk) in points {
+        if front.iter().any(|(f, _)| dominates(f, &cost) || (f.md, f.mc, f.xc) == (cost.md, cost.mc, cost.xc)) {
+            continue;
+        }
+        front.retain(|(f, _)| !dominates(&cost, f));
+        front.push((cost, ntk));
+    }
+    front.sort_by_key(|(c, _)| (c.md, c.mc, c.xc));
+    front
+}
+
+pub fn point_path(outfile: &Path, cost: &NetworkCost) -> PathBuf {
+    let stem = outfile.file_stem().unwrap().to_string_lossy();
+    outfile.with_file_name(format!("{}.md{}_mc{}_xc{}.eqn", stem, cost.md, cost.mc, cost.xc))
+}
+
+/// Writes every point of the front next to `outfile`, plus a `<stem>.pareto.csv` summary.
+pub fn write_front(front: &[(NetworkCost, String)], outfile: &Path, innodes: &str, outnodes: &str) {
+    let mut csv = String::from("md,mc,xc,cost,file\n");
+    for (cost, ntk) in front {
+        let path = point_path(outfile, cost);
+        crate::write_network(&path, innodes, outnodes, ntk);
+        csv.push_str(format!("{},{},{},{},{}\n", cost.md, cost.mc, cost.xc, cost.cost, path.display()).as_str());
+    }
+    let stem = outfile.file_stem().unwrap().to_string_lossy();
+    std::fs::write(outfile.with_file_name(format!("{}.pareto.csv", stem)), csv).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(md: u64, mc: u64, xc: u64, ntk: &str) -> (NetworkCost, String) {
+        (NetworkCost { md, mc, xc, ..NetworkCost::default() }, ntk.to_string())
+    }
+
+    fn names(front: &[(NetworkCost, String)]) -> Vec<&str> {
+        front.iter().map(|(_, ntk)| ntk.as_str()).collect()
+    }
+
+    #[test]
+    fn dominated_points_are_dropped() {
+        let front = pareto_front(vec![
+            point(3, 5, 2, "a"),
+            point(4, 4, 4, "b"),
+            point(3, 5, 3, "dominated by a"),
+            point(2, 6, 2, "c"),
+            point(4, 4, 3, "replaces b"),
+        ]);
+        assert_eq!(names(&front), vec!["c", "a", "replaces b"]);
+    }
+
+    #[test]
+    fn the_first_network_of_a_point_is_kept() {
+        let front = pareto_front(vec![point(2, 3, 1, "first"), point(2, 3, 1, "second"), point(1, 4, 1, "other")]);
+        assert_eq!(names(&front), vec!["other", "first"]);
+    }
+
+    #[test]
+    fn a_point_dominating_everything_is_the_whole_front() {
+        let front = pareto_front(vec![point(3, 3, 3, "a"), point(2, 4, 3, "b"), point(2, 3, 3, "best")]);
+        assert_eq!(names(&front), vec!["best"]);
+    }
+}
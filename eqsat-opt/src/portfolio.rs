@@ -0,0 +1,61 @@
+// Runs several extractors concurrently on one serialized e-graph and keeps the best network.
+use std::time::{Duration, Instant};
+
+use egraph_serialize::EGraph;
+use indexmap::IndexMap;
+use rayon::prelude::*;
+
+use crate::cost_model::{HeCostModel, NetworkCost};
+use crate::extraction_ser::{ExtractionResult, NetworkWriter};
+
+/// Annealing moves and beam width used by the stochastic members of the portfolio
+pub const ANNEAL_ITERS: usize = 20000;
+pub const BEAM_WIDTH: usize = 8;
+
+pub type Extractor<'a> = (String, Box<dyn Fn() -> Option<ExtractionResult> + Send + Sync + 'a>);
+
+pub struct Entry {
+    pub name: String,
+    /// Wall-clock time of the extractor, including writing its network
+    pub time: Duration,
+    pub result: Option<(NetworkCost, String)>,
+}
+
+pub fn run(
+    egraph: &EGraph,
+    extractors: Vec<Extractor>,
+    write: NetworkWriter,
+    out_net_to_eclass: &IndexMap<String, egg::Id>,
+    model: &dyn HeCostModel,
+) -> Vec<Entry> {
+    extractors
+        .into_par_iter()
+        .map(|(name, extract)| {
+            let start_time = Instant::now();
+            let result = extract().map(|mut cost_analysis| write(egraph, &mut cost_analysis, out_net_to_eclass, model));
+            Entry { name, time: Instant::now() - start_time, result }
+        })
+        .collect()
+}
+
+/// Prints how every extractor did and returns the winner under `model`.
+pub fn report<'e>(entries: &'e [Entry], model: &dyn HeCostModel) -> Option<&'e Entry> {
+    for entry in entries {
+        match &entry.result {
+            Some((cost, _)) => println!(
+                "portfolio: {} = ({},{}); batches = {}; score = {}; time = {:.2}s",
+                entry.name, cost.md, cost.mc, cost.batches, cost.scalar(model), entry.time.as_secs_f64()
+            ),
+            None => println!("portfolio: {} found nothing; time = {:.2}s", entry.name, entry.time.as_secs_f64()),
+        }
+    }
+    let score = |e: &Entry| e.result.as_ref().unwrap().0.scalar(model);
+    let winner = entries
+        .iter()
+        .filter(|e| e.result.is_some())
+        .min_by(|a, b| score(a).partial_cmp(&score(b)).unwrap());
+    if let Some(winner) = winner {
+        println!("portfolio winner: {}", winner.name);
+    }
+    winner
+}
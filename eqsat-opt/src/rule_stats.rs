@@ -0,0 +1,221 @@
+// Per-rule statistics of a saturation run: matches and applications per iteration, the e-nodes
+// each rule added, and which of those ended up in the extracted network.
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+
+use egg::*;
+use indexmap::IndexMap;
+
+use crate::common::{Prop, PropAnalysis};
+use crate::extraction_ser::parse_equation;
+
+/// Origin of e-nodes that were in the input network.
+pub const INPUT: &str = "(input)";
+/// Origin of e-nodes added outside of rule applications, i.e. by the FRAIG sweep between
+/// iterations. Constant folding adds none, even while a rule is being applied: `true` and
+/// `false` are in every e-graph from the start, so folding only merges a class into theirs.
+pub const ANALYSIS: &str = "(analysis)";
+/// Network equations that do not correspond to an e-node.
+pub const UNKNOWN: &str = "(unknown)";
+
+/// `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Default)]
+pub struct RuleLog {
+    /// Matches per rule, by iteration
+    pub matches: Vec<IndexMap<Symbol, usize>>,
+    /// Applications per rule, by iteration, from the runner's iteration reports
+    pub applied: Vec<IndexMap<Symbol, usize>>,
+    /// E-nodes with ids in the range were added by applying the rule
+    created: Vec<(Range<usize>, Symbol)>,
+    input_nodes: usize,
+}
+
+impl RuleLog {
+    pub fn new(egraph: &EGraph<Prop, PropAnalysis>) -> Self {
+        Self { matches: Vec::new(), applied: Vec::new(), created: Vec::new(), input_nodes: egraph.nodes().len() }
+    }
+
+    pub fn set_iterations(&mut self, iterations: &[Iteration<()>]) {
+        self.applied = iterations.iter().map(|it| it.applied.iter().map(|(r, n)| (*r, *n)).collect()).collect();
+    }
+
+    fn origin(&self, node: usize) -> Symbol {
+        if node < self.input_nodes {
+            return INPUT.into();
+        }
+        // ranges are recorded in increasing order
+        let i = self.created.partition_point(|(range, _)| range.end <= node);
+        match self.created.get(i) {
+            Some((range, rule)) if range.contains(&node) => *rule,
+            _ => ANALYSIS.into(),
+        }
+    }
+
+    /// Canonical e-node -> origin of its first copy, for every e-node ever added to `egraph`.
+    fn origins(&self, egraph: &EGraph<Prop, PropAnalysis>) -> HashMap<(Id, Prop), Symbol> {
+        let mut origins = HashMap::new();
+        for i in 0..egraph.nodes().len() {
+            let id = Id::from(i);
+            let node = egraph.id_to_node(id).clone().map_children(|c| egraph.find(c));
+            origins.entry((egraph.find(id), node)).or_insert_with(|| self.origin(i));
+        }
+        origins
+    }
+
+    /// Equations of `network` by the origin of the e-node they compute.
+    pub fn network_origins(&self, egraph: &EGraph<Prop, PropAnalysis>, network: &str, innodes: &str, outnodes: &str) -> IndexMap<Symbol, usize> {
+        let origins = self.origins(egraph);
+        let inputs: HashSet<&str> = innodes.split(" ").collect();
+        let outputs: HashSet<&str> = outnodes.split(" ").collect();
+        let mut counts: IndexMap<Symbol, usize> = IndexMap::new();
+        for line in network.lines().filter(|l| !l.starts_with('#')) {
+            let Some((lhs, _)) = line.split_once(" = ") else { continue };
+            if outputs.contains(lhs) {
+                continue;
+            }
+            let candidates = parse_equation(line, &inputs).map_or(vec![], |(class, nodes)| {
+                nodes.into_iter().map(|n| (egraph.find(class), n.map_children(|c| egraph.find(c)))).collect()
+            });
+            let origin = candidates
+                .iter()
+                .find_map(|key| origins.get(key).copied())
+                .unwrap_or_else(|| UNKNOWN.into());
+            *counts.entry(origin).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Writes `<stem>.rules.csv` with matches and applications per iteration and rule, and
+    /// `<stem>.rules.json` with the totals per rule and the origins of the network's e-nodes.
+    pub fn write(&self, outfile: &Path, rules: &[Symbol], network: &IndexMap<Symbol, usize>) {
+        let stem = outfile.file_stem().unwrap().to_string_lossy();
+
+        let mut csv = String::from("iteration,rule,matches,applied\n");
+        for (i, matches) in self.matches.iter().enumerate() {
+            let applied = self.applied.get(i);
+            for (rule, n) in matches {
+                let a = applied.and_then(|a| a.get(rule)).copied().unwrap_or(0);
+                csv.push_str(&format!("{},{},{},{}\n", i, rule, n, a));
+            }
+        }
+        std::fs::write(outfile.with_file_name(format!("{}.rules.csv", stem)), csv).unwrap();
+
+        let mut created: HashMap<Symbol, usize> = HashMap::new();
+        for (range, rule) in &self.created {
+            *created.entry(*rule).or_default() += range.len();
+        }
+        let total = |log: &[IndexMap<Symbol, usize>], rule: &Symbol| log.iter().filter_map(|it| it.get(rule)).sum::<usize>();
+        let rule_entries: Vec<String> = rules
+            .iter()
+            .map(|rule| {
+                format!(
+                    "    {{\"name\": {}, \"matches\": {}, \"applied\": {}, \"nodes_created\": {}, \"in_network\": {}}}",
+                    json_string(rule.as_str()),
+                    total(&self.matches, rule),
+                    total(&self.applied, rule),
+                    created.get(rule).copied().unwrap_or(0),
+                    network.get(rule).copied().unwrap_or(0)
+                )
+            })
+            .collect();
+        let network_entries: Vec<String> = network.iter().map(|(origin, n)| format!("    {}: {}", json_string(origin.as_str()), n)).collect();
+        let json = format!(
+            "{{\n  \"iterations\": {},\n  \"rules\": [\n{}\n  ],\n  \"network\": {{\n{}\n  }}\n}}\n",
+            self.matches.len(),
+            rule_entries.join(",\n"),
+            network_entries.join(",\n")
+        );
+        std::fs::write(outfile.with_file_name(format!("{}.rules.json", stem)), json).unwrap();
+    }
+}
+
+/// Wraps a scheduler to count the matches it hands out and the e-nodes each rule adds.
+pub struct Recording<S> {
+    inner: S,
+    log: Rc<RefCell<RuleLog>>,
+}
+
+impl<S> Recording<S> {
+    pub fn new(inner: S, log: Rc<RefCell<RuleLog>>) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl<S: RewriteScheduler<Prop, PropAnalysis>> RewriteScheduler<Prop, PropAnalysis> for Recording<S> {
+    fn can_stop(&mut self, iteration: usize) -> bool {
+        self.inner.can_stop(iteration)
+    }
+
+    fn search_rewrite<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<Prop, PropAnalysis>,
+        rewrite: &'a Rewrite<Prop, PropAnalysis>,
+    ) -> Vec<SearchMatches<'a, Prop>> {
+        let matches = self.inner.search_rewrite(iteration, egraph, rewrite);
+        let mut log = self.log.borrow_mut();
+        if log.matches.len() <= iteration {
+            log.matches.resize_with(iteration + 1, IndexMap::new);
+        }
+        *log.matches[iteration].entry(rewrite.name).or_default() += matches.iter().map(|m| m.substs.len()).sum::<usize>();
+        matches
+    }
+
+    fn apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<Prop, PropAnalysis>,
+        rewrite: &Rewrite<Prop, PropAnalysis>,
+        matches: Vec<SearchMatches<Prop>>,
+    ) -> usize {
+        let first = egraph.nodes().len();
+        let applied = self.inner.apply_rewrite(iteration, egraph, rewrite, matches);
+        let last = egraph.nodes().len();
+        if last > first {
+            self.log.borrow_mut().created.push((first..last, rewrite.name));
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("and-comm"), "\"and-comm\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        // Debug formatting would write \u{1} and \u{7f}, which JSON does not accept
+        assert_eq!(json_string("x\u{1}y\u{7f}\u{e9}"), "\"x\\u0001y\u{7f}\u{e9}\"");
+        assert_eq!(json_string("tab\tnl\n"), "\"tab\\tnl\\n\"");
+    }
+
+    #[test]
+    fn nodes_are_credited_by_id_range() {
+        let log = RuleLog { input_nodes: 3, created: vec![(3..5, "r1".into()), (7..8, "r2".into())], ..RuleLog::default() };
+        let origins: Vec<Symbol> = (0..9).map(|i| log.origin(i)).collect();
+        let expected = [INPUT, INPUT, INPUT, "r1", "r1", ANALYSIS, ANALYSIS, "r2", ANALYSIS];
+        assert_eq!(origins, expected.map(Symbol::from));
+    }
+}
@@ -0,0 +1,413 @@
+// Rule file parsing. One rule per line:
+//   <name>[<options>]:<lhs>=><rhs>[ if <condition> [&& <condition>]...]
+//   <name>[<options>]:<lhs><=><rhs>[ if ...]   (both directions, the reverse one named <name>-rev)
+// The bracketed options are optional and comma separated: tags (`md`, `mc`, `assoc`, ...),
+// `limit=N` (match limit), `ban=N` (ban length, in iterations) and `prio=N` (rules only run once
+// every rule of a higher priority is saturated; 0 by default).
+// Every rule is also tagged with its own name. Blank lines and `#` comments are skipped, and
+// `@include <file>` reads another rule file, relative to the including one.
+//
+// Conditions are checked against the e-class analysis before the rule is applied:
+//   const(?x)                  ?x is a constant
+//   plain(?x)                  ?x is computable in plaintext
+//   ?x != ?y, ?x == ?y         ?x and ?y are different (the same) classes
+//   depth(?a) > depth(?b)      compares minimum AND depths; also >=, <, <=, ==, != and integer literals
+// Conditions are joined with `&&`, and any of them can be negated with a leading `!`, e.g.
+// `!plain(?x)` or `!(depth(?a) > 2)`.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use egg::*;
+
+use crate::common::{DepthGuard, Prop, PropAnalysis};
+
+#[derive(Clone, Copy, Debug)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Cmp {
+    fn holds<T: Ord>(&self, a: T, b: T) -> bool {
+        match self {
+            Cmp::Lt => a < b,
+            Cmp::Le => a <= b,
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+            Cmp::Eq => a == b,
+            Cmp::Ne => a != b,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum DepthTerm {
+    Depth(Var),
+    Lit(usize),
+}
+
+#[derive(Clone, Debug)]
+enum Predicate {
+    Constant(Var),
+    Plain(Var),
+    SameClass(Var, Cmp, Var),
+    Depth(DepthTerm, Cmp, DepthTerm),
+}
+
+/// One condition of a rule, possibly negated.
+#[derive(Clone, Debug)]
+pub struct Guard {
+    negated: bool,
+    predicate: Predicate,
+}
+
+/// All conditions of a rule; the rule is applied only if every one holds.
+#[derive(Clone, Debug)]
+pub struct Guards(Vec<Guard>);
+
+impl Guard {
+    fn check(&self, egraph: &EGraph<Prop, PropAnalysis>, subst: &Subst) -> bool {
+        let data = |v: &Var| &egraph[subst[*v]].data;
+        let depth = |t: &DepthTerm| match t {
+            DepthTerm::Depth(v) => data(v).min_depth,
+            DepthTerm::Lit(l) => *l,
+        };
+        let holds = match &self.predicate {
+            Predicate::Constant(v) => data(v).constant.is_some(),
+            Predicate::Plain(v) => data(v).plain,
+            Predicate::SameClass(a, cmp, b) => cmp.holds(egraph.find(subst[*a]), egraph.find(subst[*b])),
+            Predicate::Depth(a, cmp, b) => cmp.holds(depth(a), depth(b)),
+        };
+        holds != self.negated
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        let depth_vars = |t: &DepthTerm| match t {
+            DepthTerm::Depth(v) => vec![*v],
+            DepthTerm::Lit(_) => vec![],
+        };
+        match &self.predicate {
+            Predicate::Constant(v) | Predicate::Plain(v) => vec![*v],
+            Predicate::SameClass(a, _, b) => vec![*a, *b],
+            Predicate::Depth(a, _, b) => [depth_vars(a), depth_vars(b)].concat(),
+        }
+    }
+}
+
+impl Condition<Prop, PropAnalysis> for Guards {
+    fn check(&self, egraph: &mut EGraph<Prop, PropAnalysis>, _eclass: Id, subst: &Subst) -> bool {
+        self.0.iter().all(|g| g.check(egraph, subst))
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.0.iter().flat_map(|g| g.vars()).collect()
+    }
+}
+
+fn parse_var(s: &str) -> Result<Var, String> {
+    s.trim().parse().map_err(|_| format!("expected a pattern variable; got {}", s.trim()))
+}
+
+/// `name(?x)` -> `?x`
+fn parse_call<'s>(s: &'s str, name: &str) -> Option<&'s str> {
+    s.trim().strip_prefix(name)?.trim().strip_prefix('(')?.strip_suffix(')')
+}
+
+fn parse_depth_term(s: &str) -> Result<DepthTerm, String> {
+    if let Some(v) = parse_call(s, "depth") {
+        return Ok(DepthTerm::Depth(parse_var(v)?));
+    }
+    s.trim()
+        .parse()
+        .map(DepthTerm::Lit)
+        .map_err(|_| format!("expected depth(?x) or a level; got {}", s.trim()))
+}
+
+fn parse_guard(s: &str) -> Result<Guard, String> {
+    const OPS: [(&str, Cmp); 6] =
+        [(">=", Cmp::Ge), ("<=", Cmp::Le), ("!=", Cmp::Ne), ("==", Cmp::Eq), (">", Cmp::Gt), ("<", Cmp::Lt)];
+    let s = s.trim();
+    // the negation covers the whole condition, comparisons included
+    if let Some(inner) = s.strip_prefix('!').filter(|inner| !inner.starts_with('=')) {
+        let guard = parse_guard(inner)?;
+        return Ok(Guard { negated: !guard.negated, ..guard });
+    }
+    if let Some(inner) = s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        return parse_guard(inner);
+    }
+    if let Some((op, cmp)) = OPS.iter().find(|(op, _)| s.contains(op)) {
+        let (a, b) = s.split_once(op).unwrap();
+        let predicate = if a.trim().starts_with('?') {
+            Predicate::SameClass(parse_var(a)?, *cmp, parse_var(b)?)
+        } else {
+            Predicate::Depth(parse_depth_term(a)?, *cmp, parse_depth_term(b)?)
+        };
+        if let Predicate::SameClass(_, Cmp::Lt | Cmp::Le | Cmp::Gt | Cmp::Ge, _) = predicate {
+            return Err(format!("classes can only be compared with == or !=; got {}", s));
+        }
+        return Ok(Guard { negated: false, predicate });
+    }
+    if let Some(v) = parse_call(s, "const") {
+        return Ok(Guard { negated: false, predicate: Predicate::Constant(parse_var(v)?) });
+    }
+    if let Some(v) = parse_call(s, "plain") {
+        return Ok(Guard { negated: false, predicate: Predicate::Plain(parse_var(v)?) });
+    }
+    Err(format!("unknown condition {}", s))
+}
+
+fn parse_guards(s: &str) -> Result<Guards, String> {
+    s.split("&&").map(parse_guard).collect::<Result<Vec<Guard>, String>>().map(Guards)
+}
+
+/// A rewrite with the options its rule file gave it.
+#[derive(Clone)]
+pub struct Rule {
+    pub rewrite: Rewrite<Prop, PropAnalysis>,
+    pub tags: Vec<String>,
+    pub priority: i32,
+    pub match_limit: Option<usize>,
+    pub ban_length: Option<usize>,
+}
+
+/// Which rules to load and how to apply them.
+#[derive(Clone, Debug, Default)]
+pub struct RuleOptions {
+    /// Only add results that can be as shallow as the class they join (see `DepthGuard`)
+    pub depth_guard: bool,
+    /// Keep only rules with one of these tags (all rules if empty)
+    pub enable: Vec<String>,
+    /// Drop rules with any of these tags
+    pub disable: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct RuleError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.msg)
+    }
+}
+
+/// Options between the brackets of a rule head.
+#[derive(Clone, Default)]
+struct Head {
+    tags: Vec<String>,
+    priority: i32,
+    match_limit: Option<usize>,
+    ban_length: Option<usize>,
+}
+
+fn parse_head(head: &str) -> Result<(&str, Head), String> {
+    let head = head.trim();
+    let Some((name, options)) = head.split_once('[') else {
+        return Ok((head, Head { tags: vec![head.to_string()], ..Head::default() }));
+    };
+    let name = name.trim();
+    let options = options.strip_suffix(']').ok_or(format!("unclosed options in rule {}", name))?;
+    let mut parsed = Head { tags: vec![name.to_string()], ..Head::default() };
+    let number = |key: &str, value: &str| value.trim().parse().map_err(|_| format!("bad {} in rule {}: {}", key, name, value.trim()));
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        match option.split_once('=') {
+            Some((key, value)) => match key.trim() {
+                "limit" => parsed.match_limit = Some(number(key, value)?),
+                "ban" => parsed.ban_length = Some(number(key, value)?),
+                "prio" => parsed.priority = number(key, value)? as i32,
+                _ => return Err(format!("unknown option {} in rule {}", key.trim(), name)),
+            },
+            None => parsed.tags.push(option.to_string()),
+        }
+    }
+    Ok((name, parsed))
+}
+
+fn parse_line(line: &str, options: &RuleOptions) -> Result<Vec<Rule>, String> {
+    let (head, body) = line
+        .split_once(':')
+        .ok_or("malformed rule: expected \"<name>[<options>]:<lhs>[=>|<=>]<rhs>[ if <condition>]\"")?;
+    let (name, head) = parse_head(head)?;
+    let (body, guards) = match body.split_once(" if ") {
+        Some((body, conditions)) => (body, Some(parse_guards(conditions).map_err(|e| format!("malformed condition in rule {}: {}", name, e))?)),
+        None => (body, None),
+    };
+    let pattern = |s: &str| s.trim().parse::<Pattern<Prop>>().map_err(|e| format!("bad pattern in rule {}: {}", name, e));
+    let rewrite = |name: String, lhs: Pattern<Prop>, rhs: Pattern<Prop>, guards: Option<Guards>| {
+        match (guards, options.depth_guard) {
+            (None, false) => Rewrite::new(name, lhs, rhs),
+            (None, true) => Rewrite::new(name, lhs, DepthGuard { rhs }),
+            (Some(condition), false) => Rewrite::new(name, lhs, ConditionalApplier { condition, applier: rhs }),
+            (Some(condition), true) => {
+                Rewrite::new(name, lhs, ConditionalApplier { condition, applier: DepthGuard { rhs } })
+            }
+        }
+        .map(|rewrite| Rule {
+            rewrite,
+            tags: head.tags.clone(),
+            priority: head.priority,
+            match_limit: head.match_limit,
+            ban_length: head.ban_length,
+        })
+    };
+    if let Some((lhs, rhs)) = body.split_once("<=>") {
+        let (lhs, rhs) = (pattern(lhs)?, pattern(rhs)?);
+        Ok(vec![
+            rewrite(name.to_string(), lhs.clone(), rhs.clone(), guards.clone())?,
+            rewrite(format!("{}-rev", name), rhs, lhs, guards)?,
+        ])
+    } else if let Some((lhs, rhs)) = body.split_once("=>") {
+        Ok(vec![rewrite(name.to_string(), pattern(lhs)?, pattern(rhs)?, guards)?])
+    } else {
+        Err(format!("malformed rule {}: expected <lhs>=><rhs> or <lhs><=><rhs>", name))
+    }
+}
+
+/// `including` holds the canonical paths of the files being read, outermost first.
+fn load_file(path: &Path, options: &RuleOptions, including: &mut Vec<PathBuf>, rules: &mut Vec<Rule>) -> Result<(), RuleError> {
+    let error = |line: usize, msg: String| RuleError { file: path.to_path_buf(), line, msg };
+    let contents = std::fs::read_to_string(path).map_err(|e| error(0, e.to_string()))?;
+    including.push(std::fs::canonicalize(path).map_err(|e| error(0, e.to_string()))?);
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(file) = line.strip_prefix("@include") {
+            let file = path.parent().unwrap_or(Path::new(".")).join(file.trim());
+            let canonical = std::fs::canonicalize(&file).map_err(|e| error(i + 1, format!("{}: {}", file.display(), e)))?;
+            if including.contains(&canonical) {
+                return Err(error(i + 1, format!("{} includes itself", file.display())));
+            }
+            load_file(&file, options, including, rules)?;
+            continue;
+        }
+        rules.extend(parse_line(line, options).map_err(|msg| error(i + 1, msg))?);
+    }
+    including.pop();
+    Ok(())
+}
+
+/// Loads the rule file at `path` (and the files it includes), keeps the rules the tag filters
+/// of `options` let through, and orders them by priority, highest first.
+pub fn load_rules(path: &Path, options: &RuleOptions, rules: &mut Vec<Rule>) -> Result<(), RuleError> {
+    let mut loaded = Vec::new();
+    load_file(path, options, &mut Vec::new(), &mut loaded)?;
+    let tagged = |rule: &Rule, tags: &[String]| rule.tags.iter().any(|t| tags.contains(t));
+    rules.extend(
+        loaded
+            .into_iter()
+            .filter(|r| options.enable.is_empty() || tagged(r, &options.enable))
+            .filter(|r| !tagged(r, &options.disable)),
+    );
+    rules.sort_by_key(|r| std::cmp::Reverse(r.priority));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negation_covers_comparisons() {
+        let guard = parse_guard("!(depth(?a) > 2)").unwrap();
+        assert!(guard.negated);
+        assert!(matches!(guard.predicate, Predicate::Depth(DepthTerm::Depth(_), Cmp::Gt, DepthTerm::Lit(2))));
+        let guard = parse_guard(" ! ?a == ?b").unwrap();
+        assert!(guard.negated);
+        assert!(matches!(guard.predicate, Predicate::SameClass(_, Cmp::Eq, _)));
+        let guard = parse_guard("!!const(?x)").unwrap();
+        assert!(!guard.negated);
+        assert!(matches!(guard.predicate, Predicate::Constant(_)));
+    }
+
+    #[test]
+    fn comparisons_are_not_negations() {
+        let guard = parse_guard("?a != ?b").unwrap();
+        assert!(!guard.negated);
+        assert!(matches!(guard.predicate, Predicate::SameClass(_, Cmp::Ne, _)));
+        assert!(parse_guard("!= ?b").is_err());
+        assert!(parse_guard("depth(?a) > ?b").is_err());
+        assert!(parse_guard("?a < ?b").is_err());
+    }
+
+    /// Writes `files` to a fresh directory and loads the first one.
+    fn load(test: &str, files: &[(&str, &str)], options: &RuleOptions) -> Result<Vec<Rule>, RuleError> {
+        let dir = std::env::temp_dir().join(format!("eqsat-opt-rules-{}-{}", std::process::id(), test));
+        for (name, contents) in files {
+            std::fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let mut rules = Vec::new();
+        let result = load_rules(&dir.join(files[0].0), options, &mut rules).map(|_| rules);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn load_error(test: &str, files: &[(&str, &str)]) -> RuleError {
+        load(test, files, &RuleOptions::default()).err().expect("the rule files should not load")
+    }
+
+    fn names(rules: &[Rule]) -> Vec<&str> {
+        rules.iter().map(|r| r.rewrite.name.as_str()).collect()
+    }
+
+    #[test]
+    fn heads_set_tags_limits_and_priorities() {
+        let contents = "# comment\n\ncomm[md, limit=5, ban=3]:(* ?a ?b)=>(* ?b ?a)\ndist[mc,prio=2]:(* ?a (^ ?b ?c))<=>(^ (* ?a ?b) (* ?a ?c))  # both ways\n";
+        let rules = load("heads", &[("a.rules", contents)], &RuleOptions::default()).unwrap();
+        // highest priority first
+        assert_eq!(names(&rules), ["dist", "dist-rev", "comm"]);
+        assert_eq!(rules[2].tags, ["comm", "md"]);
+        assert_eq!((rules[2].match_limit, rules[2].ban_length, rules[2].priority), (Some(5), Some(3), 0));
+        assert_eq!((rules[1].tags.clone(), rules[1].priority), (vec!["dist".to_string(), "mc".to_string()], 2));
+    }
+
+    #[test]
+    fn tags_filter_rules() {
+        let contents = "a[md]:(* ?a ?b)=>(* ?b ?a)\nb[mc]:(^ ?a ?b)=>(^ ?b ?a)\nc[md,assoc]:(* ?a (* ?b ?c))=>(* (* ?a ?b) ?c)\n";
+        let options = RuleOptions { enable: vec!["md".to_string()], disable: vec!["assoc".to_string()], ..RuleOptions::default() };
+        assert_eq!(names(&load("tags", &[("a.rules", contents)], &options).unwrap()), ["a"]);
+    }
+
+    #[test]
+    fn guards_are_attached() {
+        let contents = "fold:(^ ?a ?b)=>(* ?a ?b) if const(?a) && !(depth(?b) > 2) && ?a != ?b\n";
+        let rules = load("guards", &[("a.rules", contents)], &RuleOptions::default()).unwrap();
+        assert_eq!(names(&rules), ["fold"]);
+        let err = load_error("bad-guard", &[("a.rules", "\nfold:(^ ?a ?b)=>?a if odd(?a)\n")]);
+        assert_eq!(err.line, 2);
+        assert!(err.msg.contains("malformed condition in rule fold"), "{}", err.msg);
+    }
+
+    #[test]
+    fn includes_are_relative_and_acyclic() {
+        let files = [("a.rules", "@include sub/b.rules\na:(* ?a ?b)=>(* ?b ?a)\n"), ("sub/b.rules", "b:(^ ?a ?b)=>(^ ?b ?a)\n")];
+        assert_eq!(names(&load("includes", &files, &RuleOptions::default()).unwrap()), ["b", "a"]);
+        // the same file under another name is still a cycle
+        let err = load_error("self", &[("a.rules", "@include ./a.rules\n")]);
+        assert!(err.msg.contains("includes itself"), "{}", err.msg);
+        let err = load_error("cycle", &[("a.rules", "@include sub/b.rules\n"), ("sub/b.rules", "@include ../a.rules\n")]);
+        assert!(err.msg.contains("includes itself"), "{}", err.msg);
+        assert!(err.file.ends_with("sub/b.rules"));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let line = |contents: &str| load_error("errors", &[("a.rules", contents)]).line;
+        assert_eq!(line("a:(* ?a ?b)=>(* ?b ?a)\nno colon here\n"), 2);
+        assert_eq!(line("a[prio=high]:(* ?a ?b)=>(* ?b ?a)\n"), 1);
+        assert_eq!(line("a[speed=2]:(* ?a ?b)=>(* ?b ?a)\n"), 1);
+        assert_eq!(line("a[md:(* ?a ?b)=>(* ?b ?a)\n"), 1);
+        assert_eq!(line("\n\na:(* ?a ?b)->(* ?b ?a)\n"), 3);
+        assert_eq!(line("a:(* ?a ?b)=>(* ?b ?c)\n"), 1);
+        assert_eq!(line("@include missing.rules\n"), 1);
+    }
+}
@@ -0,0 +1,89 @@
+// Level-synchronous view of an extraction: multiplications per level, and how many
+// batched operations a SIMD evaluator needs to run them level by level.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use egg::Language;
+use egraph_serialize::EGraph;
+
+use crate::common::Prop;
+use crate::cost_model::HeCostModel;
+use crate::extraction_ser::{parse_equation, ExtractionResult};
+
+/// Entry `l` counts the gates that finish on level `l + 1`.
+pub fn level_widths(egraph: &EGraph, result: &ExtractionResult, model: &dyn HeCostModel) -> Vec<usize> {
+    let mut widths: Vec<usize> = Vec::new();
+    for (level, node_id) in result.values() {
+        // inputs can start above level 0, but are not gates
+        let node = &egraph[node_id];
+        if node.children.is_empty() || model.node_depth(egraph, node) == 0 {
+            continue;
+        }
+        if widths.len() < *level {
+            widths.resize(*level, 0);
+        }
+        widths[level - 1] += 1;
+    }
+    widths
+}
+
+/// Batched multiplications with `slots` gates per batch; without a slot limit,
+/// the number of non-empty levels.
+pub fn batches(egraph: &EGraph, result: &ExtractionResult, model: &dyn HeCostModel) -> u64 {
+    batch_count(&level_widths(egraph, result, model), model)
+}
+
+/// `batches` for the given level widths.
+pub fn batch_count(widths: &[usize], model: &dyn HeCostModel) -> u64 {
+    widths
+        .iter()
+        .map(|w| match model.simd_slots() {
+            Some(slots) => w.div_ceil(slots),
+            None => (*w > 0) as usize,
+        })
+        .sum::<usize>() as u64
+}
+
+/// Writes `<stem>.schedule` for a written network, in the format of ckt-convert's `schedule`:
+/// level `l` is one batch of the gates that finish on it (`MUL l`), followed by the equations
+/// that add no level (`LIN l`), in network order. `depth` gives the levels an equation's
+/// e-node adds; every equation of a written network is a single gate.
+pub fn write_schedule(network: &str, innodes: &str, outnodes: &str, depth: &dyn Fn(&Prop) -> usize, outfile: &Path) {
+    let inputs: HashSet<&str> = innodes.split(" ").collect();
+    let outputs: HashSet<&str> = outnodes.split(" ").collect();
+    let mut levels: HashMap<egg::Id, usize> = HashMap::new();
+    let mut mul: Vec<Vec<&str>> = vec![Vec::new()];
+    let mut lin: Vec<Vec<&str>> = vec![Vec::new()];
+    for line in network.lines().filter(|l| !l.starts_with('#')) {
+        let Some((lhs, rhs)) = line.split_once(" = ") else { continue };
+        let (level, gate) = if outputs.contains(lhs) {
+            let class = rhs.trim_end_matches(';').trim_start_matches('!').strip_prefix('n').and_then(|c| c.parse::<usize>().ok());
+            (class.map_or(0, |c| levels[&egg::Id::from(c)]), false)
+        } else {
+            let Some((class, candidates)) = parse_equation(line, &inputs) else { continue };
+            let node = &candidates[0];
+            let d = depth(node);
+            let level = node.children().iter().map(|c| levels[c]).max().unwrap_or(0) + d;
+            levels.insert(class, level);
+            (level, d > 0 && !node.is_leaf())
+        };
+        if mul.len() <= level {
+            mul.resize(level + 1, Vec::new());
+            lin.resize(level + 1, Vec::new());
+        }
+        let batch = if gate { &mut mul[level] } else { &mut lin[level] };
+        batch.push(lhs);
+    }
+
+    let mut contents = format!("LEVELS = {};\n", mul.len() - 1);
+    for level in 0..mul.len() {
+        if !mul[level].is_empty() {
+            contents.push_str(&format!("MUL {} = {};\n", level, mul[level].join(" ")));
+        }
+        if !lin[level].is_empty() {
+            contents.push_str(&format!("LIN {} = {};\n", level, lin[level].join(" ")));
+        }
+    }
+    let stem = outfile.file_stem().unwrap().to_string_lossy();
+    std::fs::write(outfile.with_file_name(format!("{}.schedule", stem)), contents).unwrap();
+}
@@ -0,0 +1,242 @@
+// Rule schedulers for saturation, selectable from the CLI.
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use egg::*;
+
+use crate::common::{Prop, PropAnalysis};
+use crate::rules::Rule;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SchedulerKind {
+    /// Every match of every rule, every iteration
+    Simple,
+    /// egg's backoff: rules matching too often are banned for a while
+    #[default]
+    Backoff,
+    /// Backoff, applying matches on the minimum-depth critical path first
+    MdPriority,
+}
+
+/// Backoff scheduler with the given defaults and the per-rule limits of the rule files.
+pub fn backoff(rules: &[Rule], match_limit: usize, ban_length: usize) -> BackoffScheduler {
+    let mut scheduler = BackoffScheduler::default()
+        .with_initial_match_limit(match_limit)
+        .with_ban_length(ban_length);
+    for rule in rules {
+        if let Some(limit) = rule.match_limit {
+            scheduler = scheduler.rule_match_limit(rule.rewrite.name, limit);
+        }
+        if let Some(length) = rule.ban_length {
+            scheduler = scheduler.rule_ban_length(rule.rewrite.name, length);
+        }
+    }
+    scheduler
+}
+
+/// Classes on a critical path of the shallowest terms of the deepest outputs.
+fn critical_classes(egraph: &EGraph<Prop, PropAnalysis>, roots: &[Id]) -> HashSet<Id> {
+    let depth = roots.iter().map(|r| egraph[*r].data.min_depth).max().unwrap_or(0);
+    let mut stack: Vec<Id> = roots.iter().map(|r| egraph.find(*r)).filter(|r| egraph[*r].data.min_depth == depth).collect();
+    let mut critical = HashSet::new();
+    while let Some(id) = stack.pop() {
+        if !critical.insert(id) {
+            continue;
+        }
+        let class_depth = egraph[id].data.min_depth;
+        for node in egraph[id].iter() {
+            let levels = egraph.analysis.levels(node, |c| egraph[c].data.plain);
+            // only operands that make this node as deep as the class's shallowest term
+            for child in node.children() {
+                if egraph[*child].data.min_depth + levels == class_depth {
+                    stack.push(egraph.find(*child));
+                }
+            }
+        }
+    }
+    critical
+}
+
+/// Applies matches in critical-path classes every iteration and the others every `period`
+/// iterations, so deep logic gets rewritten before the rest of the e-graph grows. Saturation
+/// only stops once nothing is held back.
+pub struct MdPriority {
+    backoff: BackoffScheduler,
+    roots: Vec<Id>,
+    period: usize,
+    critical: Option<(usize, HashSet<Id>)>,
+    iteration: usize,
+    deferred: bool,
+    flush_at: Option<usize>,
+}
+
+impl MdPriority {
+    pub fn new(backoff: BackoffScheduler, roots: Vec<Id>, period: usize) -> Self {
+        Self { backoff, roots, period: period.max(1), critical: None, iteration: 0, deferred: false, flush_at: None }
+    }
+}
+
+impl RewriteScheduler<Prop, PropAnalysis> for MdPriority {
+    fn can_stop(&mut self, iteration: usize) -> bool {
+        if std::mem::take(&mut self.deferred) {
+            // saturated only on the critical path; run once more with everything
+            self.flush_at = Some(iteration + 1);
+            return false;
+        }
+        RewriteScheduler::<Prop, PropAnalysis>::can_stop(&mut self.backoff, iteration)
+    }
+
+    fn search_rewrite<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<Prop, PropAnalysis>,
+        rewrite: &'a Rewrite<Prop, PropAnalysis>,
+    ) -> Vec<SearchMatches<'a, Prop>> {
+        if iteration != self.iteration {
+            self.iteration = iteration;
+            self.deferred = false;
+        }
+        let matches = self.backoff.search_rewrite(iteration, egraph, rewrite);
+        if self.flush_at == Some(iteration) || iteration.is_multiple_of(self.period) {
+            return matches;
+        }
+        if self.critical.as_ref().is_none_or(|(i, _)| *i != iteration) {
+            self.critical = Some((iteration, critical_classes(egraph, &self.roots)));
+        }
+        let critical = &self.critical.as_ref().unwrap().1;
+        let (hot, cold): (Vec<_>, Vec<_>) = matches.into_iter().partition(|m| critical.contains(&egraph.find(m.eclass)));
+        self.deferred |= !cold.is_empty();
+        hot
+    }
+
+    fn apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<Prop, PropAnalysis>,
+        rewrite: &Rewrite<Prop, PropAnalysis>,
+        matches: Vec<SearchMatches<Prop>>,
+    ) -> usize {
+        self.backoff.apply_rewrite(iteration, egraph, rewrite, matches)
+    }
+}
+
+/// Wraps a scheduler to hold back the rules of each priority (`prio=` in rule files) until the
+/// rules of every higher priority are saturated. Once let in, a priority stays in.
+pub struct Prioritized<S> {
+    inner: S,
+    priorities: HashMap<Symbol, i32>,
+    /// Rules below this priority are held back
+    threshold: i32,
+    /// Priorities still held back, highest first
+    held: Vec<i32>,
+}
+
+impl<S> Prioritized<S> {
+    pub fn new(inner: S, rules: &[Rule]) -> Self {
+        let priorities: HashMap<Symbol, i32> = rules.iter().map(|r| (r.rewrite.name, r.priority)).collect();
+        let mut held: Vec<i32> = priorities.values().copied().collect();
+        held.sort_by_key(|p| std::cmp::Reverse(*p));
+        held.dedup();
+        let threshold = if held.is_empty() { 0 } else { held.remove(0) };
+        Self { inner, priorities, threshold, held }
+    }
+}
+
+impl<S: RewriteScheduler<Prop, PropAnalysis>> RewriteScheduler<Prop, PropAnalysis> for Prioritized<S> {
+    fn can_stop(&mut self, iteration: usize) -> bool {
+        if !self.inner.can_stop(iteration) {
+            return false;
+        }
+        if self.held.is_empty() {
+            return true;
+        }
+        self.threshold = self.held.remove(0);
+        false
+    }
+
+    fn search_rewrite<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<Prop, PropAnalysis>,
+        rewrite: &'a Rewrite<Prop, PropAnalysis>,
+    ) -> Vec<SearchMatches<'a, Prop>> {
+        if self.priorities.get(&rewrite.name).is_some_and(|p| *p < self.threshold) {
+            return vec![];
+        }
+        self.inner.search_rewrite(iteration, egraph, rewrite)
+    }
+
+    fn apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<Prop, PropAnalysis>,
+        rewrite: &Rewrite<Prop, PropAnalysis>,
+        matches: Vec<SearchMatches<Prop>>,
+    ) -> usize {
+        self.inner.apply_rewrite(iteration, egraph, rewrite, matches)
+    }
+}
+
+/// Wraps a scheduler to tell the analysis which rule is applying, so that a contradiction it
+/// finds can be blamed on that rule.
+pub struct Blaming<S>(pub S);
+
+impl<S: RewriteScheduler<Prop, PropAnalysis>> RewriteScheduler<Prop, PropAnalysis> for Blaming<S> {
+    fn can_stop(&mut self, iteration: usize) -> bool {
+        self.0.can_stop(iteration)
+    }
+
+    fn search_rewrite<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<Prop, PropAnalysis>,
+        rewrite: &'a Rewrite<Prop, PropAnalysis>,
+    ) -> Vec<SearchMatches<'a, Prop>> {
+        self.0.search_rewrite(iteration, egraph, rewrite)
+    }
+
+    fn apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<Prop, PropAnalysis>,
+        rewrite: &Rewrite<Prop, PropAnalysis>,
+        matches: Vec<SearchMatches<Prop>>,
+    ) -> usize {
+        egraph.analysis.cause = Some(format!("rule {}", rewrite.name));
+        let applied = self.0.apply_rewrite(iteration, egraph, rewrite, matches);
+        egraph.analysis.cause = None;
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Rule;
+
+    fn rule(name: &str, lhs: &str, rhs: &str, priority: i32) -> Rule {
+        let (lhs, rhs): (Pattern<Prop>, Pattern<Prop>) = (lhs.parse().unwrap(), rhs.parse().unwrap());
+        Rule { rewrite: Rewrite::new(name, lhs, rhs).unwrap(), tags: vec![], priority, match_limit: None, ban_length: None }
+    }
+
+    fn applied(priorities: [i32; 2]) -> Vec<Vec<String>> {
+        let rules = vec![
+            rule("comm", "(* ?a ?b)", "(* ?b ?a)", priorities[0]),
+            rule("demorgan", "(* ?a ?b)", "(! (+ (! ?a) (! ?b)))", priorities[1]),
+        ];
+        let mut egraph: EGraph<Prop, PropAnalysis> = EGraph::default();
+        egraph.add_expr(&"(* x y)".parse().unwrap());
+        let runner = Runner::default()
+            .with_egraph(egraph)
+            .with_scheduler(Prioritized::new(SimpleScheduler, &rules))
+            .run(rules.iter().map(|r| &r.rewrite));
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        runner.iterations.iter().map(|i| i.applied.keys().map(|s| s.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn lower_priorities_wait_for_saturation() {
+        assert_eq!(applied([1, 0])[..3], [vec!["comm"], vec![], vec!["demorgan"]]);
+        assert_eq!(applied([0, 0])[0], ["comm", "demorgan"]);
+    }
+}
@@ -1,18 +1,32 @@
 use egg::*;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use std::{collections::{HashMap, HashSet}, fmt::Display, fs::File, io::{BufWriter, Write}};
 use egraph_serialize::EnodeBits;
 
-use crate::common::{Prop,PropId};
+use crate::common::{Prop,PropAnalysis,PropId};
+use crate::cost_model::HeCostModel;
+
+/// Class data type of the serialized classes computable from plaintext inputs alone.
+pub const PLAIN_TYPE: &str = "plain";
+
+/// Whether `node` is a gate with a plaintext operand, i.e. a ciphertext-plaintext (or
+/// plaintext-only) operation that costs nothing.
+pub fn is_plain(egraph: &egraph_serialize::EGraph, node: &egraph_serialize::Node) -> bool {
+    matches!(decode_op_string(&node.op), PropId::And | PropId::Or | PropId::Xor)
+        && node.children.iter().any(|c| {
+            let class = egraph_serialize::ClassId::new(c.class());
+            egraph.class_data.get(&class).is_some_and(|d| d.typ.as_deref() == Some(PLAIN_TYPE))
+        })
+}
 
 pub fn decode_op_string(op: &str) -> PropId {
     let op0 = op.chars().nth(0).unwrap();
     match op0 {
         '*' => PropId::And,
+        '+' => PropId::Or,
         '!' => PropId::Not,
         '^' => PropId::Xor,
-        't' => PropId::Lit,
-        'f' => PropId::Lit,
+        't' | 'f' if op == "true" || op == "false" => PropId::Lit,
         _   => if op == "->" { PropId::Ct } else { PropId::Sym },
     }
 }
@@ -24,6 +38,11 @@ pub fn decode_enode(new_to_old: &HashMap<Id, Id>, enode: &egraph_serialize::Node
             let b = Id::from(enode.children[1].class() as usize);
             Some(Prop::And([*new_to_old.get(&a)?, *new_to_old.get(&b)?]))
         }
+        PropId::Or => {
+            let a = Id::from(enode.children[0].class() as usize);
+            let b = Id::from(enode.children[1].class() as usize);
+            Some(Prop::Or([*new_to_old.get(&a)?, *new_to_old.get(&b)?]))
+        }
         PropId::Xor => {
             let a = Id::from(enode.children[0].class() as usize);
             let b = Id::from(enode.children[1].class() as usize);
@@ -59,7 +78,7 @@ pub fn decode_enode(new_to_old: &HashMap<Id, Id>, enode: &egraph_serialize::Node
 /// containing the selection of Classes -> Nodes. It also *only* contains e-classes that should
 /// be in the final network.
 pub fn deserialize_into_existing(
-    egraph: &mut EGraph<Prop, ()>,
+    egraph: &mut EGraph<Prop, PropAnalysis>,
     new_to_old: &mut HashMap<Id, Id>,
     egraph_ser: egraph_serialize::EGraph,
     extraction_result: &IndexMap<egraph_serialize::ClassId,(usize,egraph_serialize::NodeId)>
@@ -133,35 +152,41 @@ where
     reachable
 }
 
-pub fn serialize_in_mem<'a, L, A>(egraph: &EGraph<L, A>, root_eclasses: impl IntoIterator<Item=&'a Id>) -> egraph_serialize::EGraph
-where
-    L: Language + Display,
-    A: Analysis<L>,
-{
+pub fn serialize_in_mem<'a>(egraph: &EGraph<Prop, PropAnalysis>, root_eclasses: impl IntoIterator<Item=&'a Id>, model: &dyn HeCostModel) -> egraph_serialize::EGraph {
     use egraph_serialize::*;
     let mut out = EGraph::default();
-    let root_eclasses: Vec<&Id> = root_eclasses.into_iter().collect();
-    let reachable = filter_dead(egraph, &root_eclasses);
+    // several outputs may sit in the same (canonical) class; each root is listed once
+    let root_eclasses: IndexSet<Id> = root_eclasses.into_iter().map(|id| egraph.find(*id)).collect();
+    let reachable = filter_dead(egraph, &root_eclasses.iter().collect::<Vec<&Id>>());
     for class in egraph.classes() {
         if egraph.find(class.id) != class.id || !reachable.contains(&class.id) {
             continue;
         }
         for (i, node) in class.nodes.iter().enumerate() {
+            let op = node.to_string();
+            let mut cost = model.gate_cost(&decode_op_string(&op));
+            let gate = matches!(node, Prop::And(_) | Prop::Or(_) | Prop::Xor(_));
+            if gate && node.any(|c| egraph[c].data.plain) {
+                cost = 0.0;
+            }
             out.add_node(
                 NodeId::new(i as u32, class.id.into()),
                 Node {
-                    op: node.to_string(),
+                    op,
                     children: node
                         .children()
                         .iter()
                         .map(|id| NodeId::new(0, (egraph.find(*id)).into()))
                         .collect(),
                     eclass: ClassId::new(class.id.into()),
-                    cost: Cost::new(if node.to_string() == "*" { 1.0 } else {0.0}).unwrap(),
+                    cost: Cost::new(cost).unwrap(),
                     subsumed: false
                 },
             )
         }
+        if class.data.plain {
+            out.class_data.insert(ClassId::new(class.id.into()), ClassData { typ: Some(PLAIN_TYPE.to_string()) });
+        }
     }
     out.root_eclasses = root_eclasses.iter().map(|x| x.to_string().into()).collect();
     out
@@ -0,0 +1,425 @@
+// Seeded stochastic extractors over the serialized e-graph (simulated annealing, beam search).
+// Both start from an existing extraction and move one class at a time; the score of the
+// extraction is kept up to date along each move instead of being recomputed from scratch.
+use std::collections::{HashMap, HashSet};
+
+use clap::ValueEnum;
+use egraph_serialize::{ClassId, EGraph, NodeId};
+use indexmap::IndexSet;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::cost_model::HeCostModel;
+use crate::extraction_ser::{levelize, ExtractionResult};
+use crate::schedule;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Method {
+    /// Simulated annealing over single-class node swaps
+    Anneal,
+    /// Beam search over classes, outputs first
+    Beam,
+}
+
+type Choices = HashMap<ClassId, NodeId>;
+
+/// The node chosen by `start` in every class it covers, the first node elsewhere.
+fn initial_choices(egraph: &EGraph, start: &ExtractionResult) -> Choices {
+    egraph
+        .classes()
+        .iter()
+        .map(|(cid, class)| (*cid, start.get(cid).map_or(class.nodes[0], |(_, n)| *n)))
+        .collect()
+}
+
+/// Classes whose nodes use each class as a child.
+fn parents(egraph: &EGraph) -> HashMap<ClassId, Vec<ClassId>> {
+    let mut parents: HashMap<ClassId, Vec<ClassId>> = HashMap::new();
+    for (cid, class) in egraph.classes() {
+        let children: HashSet<&ClassId> = class.nodes.iter().flat_map(|n| egraph[n].children.iter().map(|c| egraph.nid_to_cid(c))).collect();
+        for child in children {
+            parents.entry(*child).or_default().push(*cid);
+        }
+    }
+    parents
+}
+
+/// An extraction with its levels, cost and level widths, updated as single classes change:
+/// levels along the changed class's fan-out, the rest along the classes it starts or stops using.
+#[derive(Clone)]
+struct State<'a> {
+    egraph: &'a EGraph,
+    model: &'a dyn HeCostModel,
+    roots: &'a [ClassId],
+    parents: &'a HashMap<ClassId, Vec<ClassId>>,
+    choices: Choices,
+    /// Classes of the extraction, with how often the roots and the chosen nodes use them
+    uses: HashMap<ClassId, usize>,
+    levels: HashMap<ClassId, usize>,
+    cost: f64,
+    /// Gates finishing on each level, as in `schedule::level_widths`
+    widths: Vec<usize>,
+    /// Classes of the extraction with a choice to make
+    movable: IndexSet<ClassId>,
+}
+
+impl<'a> State<'a> {
+    /// `None` if the outputs reach a cycle.
+    fn new(
+        egraph: &'a EGraph,
+        model: &'a dyn HeCostModel,
+        roots: &'a [ClassId],
+        parents: &'a HashMap<ClassId, Vec<ClassId>>,
+        choices: Choices,
+    ) -> Option<Self> {
+        levelize(egraph, roots, &choices, model)?;
+        let mut state = State {
+            egraph,
+            model,
+            roots,
+            parents,
+            choices,
+            uses: HashMap::new(),
+            levels: HashMap::new(),
+            cost: 0.0,
+            widths: Vec::new(),
+            movable: IndexSet::new(),
+        };
+        for root in roots {
+            state.acquire(*root);
+        }
+        Some(state)
+    }
+
+    /// Same score as the cost model gives the finished network.
+    fn score(&self) -> f64 {
+        let md = self.roots.iter().map(|r| self.levels[r]).max().unwrap_or(0);
+        let batches = schedule::batch_count(&self.widths, self.model);
+        self.model.combine(md as u64, self.cost) + self.model.batch_penalty(batches)
+    }
+
+    fn result(&self) -> ExtractionResult {
+        levelize(self.egraph, self.roots, &self.choices, self.model).unwrap().2
+    }
+
+    fn children(&self, class: &ClassId) -> Vec<ClassId> {
+        self.egraph[&self.choices[class]].children.iter().map(|c| *self.egraph.nid_to_cid(c)).collect()
+    }
+
+    fn level(&self, class: &ClassId) -> usize {
+        let node = &self.egraph[&self.choices[class]];
+        self.children(class).iter().map(|c| self.levels[c]).max().unwrap_or(0) + self.model.node_depth(self.egraph, node)
+    }
+
+    /// Adds the chosen node of `class` to the totals at its current level, or removes it.
+    fn count(&mut self, class: &ClassId, add: bool) {
+        let node = &self.egraph[&self.choices[class]];
+        let cost = self.model.node_cost(self.egraph, node);
+        self.cost += if add { cost } else { -cost };
+        // inputs can start above level 0, but are not gates
+        if node.children.is_empty() || self.model.node_depth(self.egraph, node) == 0 {
+            return;
+        }
+        let level = self.levels[class];
+        if add {
+            if self.widths.len() < level {
+                self.widths.resize(level, 0);
+            }
+            self.widths[level - 1] += 1;
+        } else {
+            self.widths[level - 1] -= 1;
+        }
+    }
+
+    /// Uses `class` once more; a class used for the first time brings in its own children.
+    fn acquire(&mut self, class: ClassId) {
+        let mut stack = vec![(class, false)];
+        while let Some((class, expanded)) = stack.pop() {
+            if expanded {
+                self.levels.insert(class, self.level(&class));
+                self.count(&class, true);
+                if self.egraph[&class].nodes.len() > 1 {
+                    self.movable.insert(class);
+                }
+                continue;
+            }
+            let uses = self.uses.entry(class).or_insert(0);
+            *uses += 1;
+            if *uses == 1 {
+                stack.push((class, true));
+                stack.extend(self.children(&class).into_iter().map(|c| (c, false)));
+            }
+        }
+    }
+
+    /// Uses `class` once less; a class no longer used lets go of its children.
+    fn release(&mut self, class: ClassId) {
+        let mut stack = vec![class];
+        while let Some(class) = stack.pop() {
+            let uses = self.uses.get_mut(&class).unwrap();
+            *uses -= 1;
+            if *uses == 0 {
+                self.uses.remove(&class);
+                self.count(&class, false);
+                self.levels.remove(&class);
+                self.movable.swap_remove(&class);
+                stack.extend(self.children(&class));
+            }
+        }
+    }
+
+    /// Classes of the extraction whose chosen node uses `class`.
+    fn dependents(&self, class: &ClassId) -> Vec<ClassId> {
+        self.parents
+            .get(class)
+            .into_iter()
+            .flatten()
+            .filter(|p| self.uses.contains_key(*p) && self.children(p).contains(class))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether `class` can choose `node` without a cycle: no class of the extraction that
+    /// depends on `class` may be below `node`, and the classes `node` brings in must be acyclic.
+    fn acyclic(&self, class: &ClassId, node: NodeId) -> bool {
+        let mut fanout: HashSet<ClassId> = HashSet::from([*class]);
+        let mut queue = vec![*class];
+        while let Some(k) = queue.pop() {
+            for p in self.dependents(&k) {
+                if fanout.insert(p) {
+                    queue.push(p);
+                }
+            }
+        }
+
+        let (mut visiting, mut done): (HashSet<ClassId>, HashSet<ClassId>) = (HashSet::new(), HashSet::new());
+        let mut stack: Vec<(ClassId, bool)> =
+            self.egraph[&node].children.iter().map(|c| (*self.egraph.nid_to_cid(c), false)).collect();
+        while let Some((k, expanded)) = stack.pop() {
+            if expanded {
+                visiting.remove(&k);
+                done.insert(k);
+                continue;
+            }
+            // the cone of a class already in the extraction is acyclic
+            if self.uses.contains_key(&k) {
+                if fanout.contains(&k) {
+                    return false;
+                }
+                continue;
+            }
+            if done.contains(&k) {
+                continue;
+            }
+            if !visiting.insert(k) {
+                return false;
+            }
+            stack.push((k, true));
+            stack.extend(self.children(&k).into_iter().map(|c| (c, false)));
+        }
+        true
+    }
+
+    /// Makes `class`, which the extraction uses, choose `node`; `false` (and no change) if that
+    /// would make a cycle.
+    fn choose(&mut self, class: &ClassId, node: NodeId) -> bool {
+        if !self.acyclic(class, node) {
+            return false;
+        }
+        let old_children = self.children(class);
+        self.count(class, false);
+        self.choices.insert(*class, node);
+        // children are taken before the old ones are let go, so shared ones stay
+        for c in self.children(class) {
+            self.acquire(c);
+        }
+        for c in old_children {
+            self.release(c);
+        }
+        self.levels.insert(*class, self.level(class));
+        self.count(class, true);
+
+        let mut queue = self.dependents(class);
+        while let Some(k) = queue.pop() {
+            let level = self.level(&k);
+            if level == self.levels[&k] {
+                continue;
+            }
+            self.count(&k, false);
+            self.levels.insert(k, level);
+            self.count(&k, true);
+            queue.extend(self.dependents(&k));
+        }
+        true
+    }
+}
+
+pub fn anneal(
+    egraph: &EGraph,
+    roots: &[ClassId],
+    start: &ExtractionResult,
+    model: &dyn HeCostModel,
+    seed: u64,
+    iters: usize,
+) -> ExtractionResult {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let parents = parents(egraph);
+    let mut state = State::new(egraph, model, roots, &parents, initial_choices(egraph, start)).expect("starting extraction is cyclic");
+    let mut score = state.score();
+    let (mut best_score, mut best) = (score, state.choices.clone());
+
+    // start hot enough to take moves a few percent worse than the start,
+    // and cool down geometrically to a thousandth of that
+    let mut temp = (0.05 * score).max(1e-3);
+    let cooling = 1e-3f64.powf(1.0 / iters.max(1) as f64);
+    for _ in 0..iters {
+        temp *= cooling;
+        // only classes used by the current extraction, with a choice to make, can move
+        if state.movable.is_empty() {
+            break;
+        }
+        let class = state.movable[rng.random_range(0..state.movable.len())];
+        let nodes = &egraph[&class].nodes;
+        let node = nodes[rng.random_range(0..nodes.len())];
+        let old = state.choices[&class];
+        if old == node || !state.choose(&class, node) {
+            continue;
+        }
+        let new_score = state.score();
+        if new_score <= score || rng.random::<f64>() < ((score - new_score) / temp).exp() {
+            score = new_score;
+            if score < best_score {
+                best_score = score;
+                best = state.choices.clone();
+            }
+        } else {
+            state.choose(&class, old);
+        }
+    }
+    state.choices = best;
+    state.result()
+}
+
+pub fn beam(
+    egraph: &EGraph,
+    roots: &[ClassId],
+    start: &ExtractionResult,
+    model: &dyn HeCostModel,
+    seed: u64,
+    width: usize,
+) -> ExtractionResult {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let parents = parents(egraph);
+    let state = State::new(egraph, model, roots, &parents, initial_choices(egraph, start)).expect("starting extraction is cyclic");
+
+    // outputs first: choices near the outputs decide which classes below are needed at all
+    let order: Vec<ClassId> = state.result().keys().rev().filter(|c| egraph[*c].nodes.len() > 1).cloned().collect();
+    let mut states: Vec<(f64, State)> = vec![(state.score(), state)];
+    for class in order {
+        // (score, state it comes from, node to choose), alternatives being scored by moving
+        // the state there and back
+        let mut next: Vec<(f64, usize, Option<NodeId>)> = Vec::new();
+        for (i, (score, state)) in states.iter_mut().enumerate() {
+            if state.uses.contains_key(&class) {
+                let current = state.choices[&class];
+                for node in &egraph[&class].nodes {
+                    if *node == current || !state.choose(&class, *node) {
+                        continue;
+                    }
+                    next.push((state.score(), i, Some(*node)));
+                    state.choose(&class, current);
+                }
+            }
+            next.push((*score, i, None));
+        }
+        // ties are broken by the seed
+        next.shuffle(&mut rng);
+        next.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        next.truncate(width.max(1));
+
+        // the last survivor of each state takes it over, the others get a copy
+        let last: HashMap<usize, usize> = next.iter().enumerate().map(|(j, (_, i, _))| (*i, j)).collect();
+        let mut parents: Vec<Option<State>> = states.into_iter().map(|(_, s)| Some(s)).collect();
+        states = next
+            .into_iter()
+            .enumerate()
+            .map(|(j, (score, i, node))| {
+                let mut state = if last[&i] == j { parents[i].take().unwrap() } else { parents[i].clone().unwrap() };
+                if let Some(node) = node {
+                    state.choose(&class, node);
+                }
+                (score, state)
+            })
+            .collect();
+    }
+    states.swap_remove(0).1.result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Prop, PropAnalysis};
+    use crate::cost_model::{CostProfile, SimdBatched};
+    use crate::serde;
+    use egg::{rewrite as rw, RecExpr, Runner};
+
+    fn saturated(model: &dyn HeCostModel) -> EGraph {
+        let rules: Vec<egg::Rewrite<Prop, PropAnalysis>> = vec![
+            rw!("comm-and"; "(* ?a ?b)" => "(* ?b ?a)"),
+            rw!("assoc-and"; "(* ?a (* ?b ?c))" => "(* (* ?a ?b) ?c)"),
+            rw!("distr"; "(* ?a (^ ?b ?c))" => "(^ (* ?a ?b) (* ?a ?c))"),
+            rw!("factor"; "(^ (* ?a ?b) (* ?a ?c))" => "(* ?a (^ ?b ?c))"),
+        ];
+        let expr: RecExpr<Prop> = "(^ (* a (* b (* c d))) (* a (^ b e)))".parse().unwrap();
+        let runner = Runner::default().with_expr(&expr).with_iter_limit(4).run(&rules);
+        serde::serialize_in_mem(&runner.egraph, &runner.roots, model)
+    }
+
+    fn full_score(egraph: &EGraph, roots: &[ClassId], choices: &Choices, model: &dyn HeCostModel) -> (f64, ExtractionResult) {
+        let (md, cost, result) = levelize(egraph, roots, choices, model).unwrap();
+        let batches = schedule::batches(egraph, &result, model);
+        (model.combine(md as u64, cost) + model.batch_penalty(batches), result)
+    }
+
+    #[test]
+    fn incremental_score_matches_full_evaluation() {
+        let model = SimdBatched { base: CostProfile::Ckks.model(0.0), slots: 2, weight: 0.5 };
+        let egraph = saturated(&model);
+        let roots = egraph.root_eclasses.clone();
+        let parents = parents(&egraph);
+        let start = crate::global_greedy_dag::mc_extract(&egraph, &roots, HashMap::new(), &model);
+        let mut state = State::new(&egraph, &model, &roots, &parents, initial_choices(&egraph, &start)).unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let mut moves = 0;
+        for _ in 0..500 {
+            let class = state.movable[rng.random_range(0..state.movable.len())];
+            let nodes = &egraph[&class].nodes;
+            if !state.choose(&class, nodes[rng.random_range(0..nodes.len())]) {
+                continue;
+            }
+            moves += 1;
+            let (score, result) = full_score(&egraph, &roots, &state.choices, &model);
+            assert!((state.score() - score).abs() < 1e-9);
+            assert_eq!(state.uses.keys().collect::<HashSet<_>>(), result.keys().collect::<HashSet<_>>());
+        }
+        assert!(moves > 0);
+    }
+
+    #[test]
+    fn stochastic_extractors_never_lose_to_the_start() {
+        let model = CostProfile::Bgv.model(0.0);
+        let egraph = saturated(model.as_ref());
+        let roots = egraph.root_eclasses.clone();
+        let start = crate::global_greedy_dag::mc_extract(&egraph, &roots, HashMap::new(), model.as_ref());
+        let choices = |r: &ExtractionResult| r.iter().map(|(c, (_, n))| (*c, *n)).collect::<Choices>();
+        let (start_score, _) = full_score(&egraph, &roots, &choices(&start), model.as_ref());
+        for result in [
+            anneal(&egraph, &roots, &start, model.as_ref(), 7, 200),
+            beam(&egraph, &roots, &start, model.as_ref(), 7, 4),
+        ] {
+            let (score, _) = full_score(&egraph, &roots, &choices(&result), model.as_ref());
+            assert!(score <= start_score);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// FRAIG-style sweeping on top of the e-graph: classes whose simulation signatures are equal
+// (or complementary) are merged once an exact check proves them equivalent.
+use std::collections::HashMap;
+
+use egg::*;
+use varisat::{ExtendFormula, Lit, Solver};
+
+use crate::common::{Prop, PropAnalysis, Sig};
+
+/// Cones with at most this many inputs are checked by exhaustive simulation, larger ones by SAT.
+const EXHAUSTIVE_SUPPORT: usize = 16;
+/// Candidate pairs whose cones are larger than this are left alone.
+const MAX_CONE: usize = 20000;
+
+/// The signature or its complement, whichever simulates the first pattern to 0,
+/// and whether it was complemented.
+fn normalize(sig: &Sig) -> (Sig, bool) {
+    if sig[0] & 1 == 1 {
+        (sig.map(|w| !w), true)
+    } else {
+        (*sig, false)
+    }
+}
+
+/// The nodes of a term for each of `roots`, children before parents.
+fn cone(egraph: &EGraph<Prop, PropAnalysis>, best: &Extractor<'_, AstSize, Prop, PropAnalysis>, roots: &[Id]) -> Vec<(Id, Prop)> {
+    let mut order: Vec<(Id, Prop)> = Vec::new();
+    let mut seen: HashMap<Id, bool> = HashMap::new();
+    let mut stack: Vec<(Id, bool)> = roots.iter().map(|r| (egraph.find(*r), false)).collect();
+    while let Some((id, expanded)) = stack.pop() {
+        if expanded {
+            order.push((id, best.find_best_node(id).clone().map_children(|c| egraph.find(c))));
+            continue;
+        }
+        if seen.insert(id, true).is_some() {
+            continue;
+        }
+        stack.push((id, true));
+        for child in best.find_best_node(id).children() {
+            stack.push((egraph.find(*child), false));
+        }
+    }
+    order
+}
+
+fn support(cone: &[(Id, Prop)]) -> Vec<Symbol> {
+    let mut inputs: Vec<Symbol> = cone
+        .iter()
+        .filter_map(|(_, n)| match n {
+            Prop::Symbol(s) => Some(*s),
+            _ => None,
+        })
+        .collect();
+    inputs.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    inputs.dedup();
+    inputs
+}
+
+/// Whether `a` equals `b` (or its complement) on every input pattern.
+fn equal_exhaustive(cone: &[(Id, Prop)], inputs: &[Symbol], a: Id, b: Id, complement: bool) -> bool {
+    let patterns = 1usize << inputs.len();
+    let words = patterns.div_ceil(64);
+    let mask = if patterns < 64 { (1u64 << patterns) - 1 } else { !0 };
+    let input_word = |i: usize, w: usize| -> u64 {
+        (0..64).filter(|bit| ((w * 64 + bit) >> i) & 1 == 1).fold(0, |acc, bit| acc | (1 << bit))
+    };
+    let mut values: HashMap<Id, Vec<u64>> = HashMap::new();
+    for (id, node) in cone {
+        let v = |c: &Id| &values[c];
+        let value: Vec<u64> = match node {
+            Prop::Symbol(s) => {
+                let i = inputs.iter().position(|x| x == s).unwrap();
+                (0..words).map(|w| input_word(i, w)).collect()
+            }
+            Prop::Bool(b) => vec![if *b { !0 } else { 0 }; words],
+            Prop::Int(n) => vec![if *n != 0 { !0 } else { 0 }; words],
+            Prop::Not(x) | Prop::Connect(x) => {
+                let invert = if matches!(node, Prop::Not(_)) { !0 } else { 0 };
+                v(x).iter().map(|w| w ^ invert).collect()
+            }
+            Prop::And([x, y]) => v(x).iter().zip(v(y)).map(|(p, q)| p & q).collect(),
+            Prop::Or([x, y]) => v(x).iter().zip(v(y)).map(|(p, q)| p | q).collect(),
+            Prop::Xor([x, y]) => v(x).iter().zip(v(y)).map(|(p, q)| p ^ q).collect(),
+            Prop::Concat(_) | Prop::Concat2(_) => return false,
+        };
+        values.insert(*id, value);
+    }
+    let invert = if complement { !0 } else { 0 };
+    values[&a].iter().zip(&values[&b]).all(|(p, q)| (p ^ q ^ invert) & mask == 0)
+}
+
+/// Whether `a` equals `b` (or its complement) for all inputs, by refuting a miter.
+fn equal_sat(cone: &[(Id, Prop)], a: Id, b: Id, complement: bool) -> bool {
+    let mut solver = Solver::new();
+    let mut lits: HashMap<Id, Lit> = HashMap::new();
+    let mut inputs: HashMap<Symbol, Lit> = HashMap::new();
+    for (id, node) in cone {
+        let lit = match node {
+            Prop::Symbol(s) => *inputs.entry(*s).or_insert_with(|| solver.new_lit()),
+            Prop::Not(x) => !lits[x],
+            Prop::Connect(x) => lits[x],
+            Prop::Bool(_) | Prop::Int(_) => {
+                let t = solver.new_lit();
+                let value = matches!(node, Prop::Bool(true)) || matches!(node, Prop::Int(n) if *n != 0);
+                solver.add_clause(&[t ^ !value]);
+                t
+            }
+            Prop::And([x, y]) | Prop::Or([x, y]) => {
+                // OR is an AND of complements, complemented
+                let inv = matches!(node, Prop::Or(_));
+                let (x, y) = (lits[x] ^ inv, lits[y] ^ inv);
+                let t = solver.new_lit();
+                solver.add_clause(&[!t, x]);
+                solver.add_clause(&[!t, y]);
+                solver.add_clause(&[t, !x, !y]);
+                t ^ inv
+            }
+            Prop::Xor([x, y]) => {
+                let (x, y) = (lits[x], lits[y]);
+                let t = solver.new_lit();
+                solver.add_clause(&[!t, x, y]);
+                solver.add_clause(&[!t, !x, !y]);
+                solver.add_clause(&[t, !x, y]);
+                solver.add_clause(&[t, x, !y]);
+                t
+            }
+            Prop::Concat(_) | Prop::Concat2(_) => return false,
+        };
+        lits.insert(*id, lit);
+    }
+    // satisfiable iff some input pattern tells the two apart
+    let (x, y) = (lits[&a], lits[&b] ^ complement);
+    solver.add_clause(&[x, y]);
+    solver.add_clause(&[!x, !y]);
+    !solver.solve().unwrap()
+}
+
+/// Merges every pair of classes proven equivalent (or complementary) among those with matching
+/// signatures, and returns the number of merges. The e-graph is rebuilt afterwards.
+pub fn fraig_sweep(egraph: &mut EGraph<Prop, PropAnalysis>) -> usize {
+    let mut buckets: HashMap<Sig, Vec<(Id, bool)>> = HashMap::new();
+    for class in egraph.classes() {
+        if class.data.constant.is_some() {
+            // constants are already merged by folding
+            continue;
+        }
+        if let Some(sig) = &class.data.sig {
+            let (sig, complement) = normalize(sig);
+            buckets.entry(sig).or_default().push((class.id, complement));
+        }
+    }
+
+    let mut merges: Vec<(Id, Id, bool)> = Vec::new();
+    {
+        let best = Extractor::new(egraph, AstSize);
+        for bucket in buckets.values().filter(|b| b.len() > 1) {
+            // the smallest term is the representative, so merges point toward shallow logic
+            let (rep, rep_phase) = *bucket.iter().min_by_key(|(id, _)| best.find_best_cost(*id)).unwrap();
+            for (id, phase) in bucket {
+                if *id == rep {
+                    continue;
+                }
+                let nodes = cone(egraph, &best, &[rep, *id]);
+                if nodes.len() > MAX_CONE {
+                    continue;
+                }
+                let complement = rep_phase != *phase;
+                let inputs = support(&nodes);
+                let equal = if inputs.len() <= EXHAUSTIVE_SUPPORT {
+                    equal_exhaustive(&nodes, &inputs, rep, *id, complement)
+                } else {
+                    equal_sat(&nodes, rep, *id, complement)
+                };
+                if equal {
+                    merges.push((rep, *id, complement));
+                }
+            }
+        }
+    }
+
+    for (rep, id, complement) in &merges {
+        let target = if *complement { egraph.add(Prop::Not(*rep)) } else { *rep };
+        egraph.union(target, *id);
+    }
+    egraph.rebuild();
+    merges.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a cone from nodes over the earlier nodes' indices.
+    fn cone_of(nodes: Vec<Prop>) -> Vec<(Id, Prop)> {
+        nodes.into_iter().enumerate().map(|(i, n)| (Id::from(i), n)).collect()
+    }
+
+    fn both(cone: &[(Id, Prop)], a: usize, b: usize, complement: bool) -> (bool, bool) {
+        let inputs = support(cone);
+        let (a, b) = (Id::from(a), Id::from(b));
+        (equal_exhaustive(cone, &inputs, a, b, complement), equal_sat(cone, a, b, complement))
+    }
+
+    fn sym(s: &str) -> Prop {
+        Prop::Symbol(Symbol::new(s))
+    }
+
+    #[test]
+    fn miters_agree_on_small_cones() {
+        let id = Id::from;
+        let cone = cone_of(vec![
+            sym("a"),
+            sym("b"),
+            Prop::And([id(0), id(1)]),
+            Prop::Not(id(0)),
+            Prop::Not(id(1)),
+            // !a + !b = !(a * b)
+            Prop::Or([id(3), id(4)]),
+            Prop::Xor([id(0), id(1)]),
+            Prop::Or([id(0), id(1)]),
+            Prop::Not(id(2)),
+            // (a + b) * !(a * b) = a ^ b
+            Prop::And([id(7), id(8)]),
+            Prop::Bool(false),
+            Prop::Xor([id(0), id(0)]),
+        ]);
+        assert_eq!(both(&cone, 2, 5, true), (true, true));
+        assert_eq!(both(&cone, 2, 5, false), (false, false));
+        assert_eq!(both(&cone, 6, 9, false), (true, true));
+        assert_eq!(both(&cone, 6, 7, false), (false, false));
+        assert_eq!(both(&cone, 6, 7, true), (false, false));
+        assert_eq!(both(&cone, 10, 11, false), (true, true));
+    }
+
+    #[test]
+    fn miters_check_every_pattern_of_wide_cones() {
+        // parity of 7 inputs in two orders, and one that misses the last input: 128 patterns
+        let names = ["a", "b", "c", "d", "e", "f", "g"];
+        let mut nodes: Vec<Prop> = names.iter().map(|n| sym(n)).collect();
+        let chain = |nodes: &mut Vec<Prop>, order: &[usize]| {
+            let mut acc = Id::from(order[0]);
+            for i in &order[1..] {
+                nodes.push(Prop::Xor([acc, Id::from(*i)]));
+                acc = Id::from(nodes.len() - 1);
+            }
+            usize::from(acc)
+        };
+        let forward = chain(&mut nodes, &[0, 1, 2, 3, 4, 5, 6]);
+        let backward = chain(&mut nodes, &[6, 5, 4, 3, 2, 1, 0]);
+        let partial = chain(&mut nodes, &[0, 1, 2, 3, 4, 5]);
+        let cone = cone_of(nodes);
+        assert_eq!(both(&cone, forward, backward, false), (true, true));
+        assert_eq!(both(&cone, forward, partial, false), (false, false));
+        assert_eq!(both(&cone, forward, partial, true), (false, false));
+    }
+}
@@ -0,0 +1,268 @@
+// Optimization traces recorded from mockturtle. Nodes are numbered as in an AIG/XAG: 0 is the
+// constant false and 1..=#inputs the primary inputs. One instruction per line:
+//   X|A <n> <ac> <a> <bc> <b>   node n = a XOR/AND b, with a (b) complemented if ac (bc) is 1
+//   O <ind> <compl> <n>         output ind is n (complemented if compl is 1); replaces an earlier O
+//   U <c> <compl> <n>           node c is equivalent to n (complemented if compl is 1)
+// Instructions only count once a COMMIT follows them (or the trace ends); a FORGET drops those
+// since the last COMMIT unless nothing was committed yet, and other lines starting with COM are
+// ignored. U instructions and O instructions that replace an output are the trace's rewrite steps.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+
+/// A node, possibly complemented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Lit {
+    pub node: usize,
+    pub compl: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Insn {
+    Gate { n: usize, xor: bool, a: Lit, b: Lit },
+    Output { ind: usize, lit: Lit },
+    Union { c: usize, lit: Lit },
+}
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Insn::Gate { n, xor, a, b } => {
+                write!(f, "{} {} {} {} {} {}", if *xor { "X" } else { "A" }, n, a.compl as u32, a.node, b.compl as u32, b.node)
+            }
+            Insn::Output { ind, lit } => write!(f, "O {} {} {}", ind, lit.compl as u32, lit.node),
+            Insn::Union { c, lit } => write!(f, "U {} {} {}", c, lit.compl as u32, lit.node),
+        }
+    }
+}
+
+/// A problem with a trace, at a line of the trace file.
+#[derive(Debug)]
+pub struct TraceError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trace line {}: {}", self.line, self.msg)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    /// Committed instructions, in order
+    pub insns: Vec<Insn>,
+    /// Trace file line of each instruction
+    pub lines: Vec<usize>,
+    /// Instructions dropped by FORGET
+    pub forgotten: usize,
+}
+
+fn parse_insn(insn: &str) -> Option<Insn> {
+    let mut fields = insn.split_whitespace();
+    let op = fields.next()?;
+    let nums: Vec<usize> = fields.map(|f| f.parse().ok()).collect::<Option<_>>()?;
+    let lit = |compl: usize, node: usize| (compl <= 1).then_some(Lit { node, compl: compl == 1 });
+    match (op, &nums[..]) {
+        ("X" | "A", [n, ac, a, bc, b]) => Some(Insn::Gate { n: *n, xor: op == "X", a: lit(*ac, *a)?, b: lit(*bc, *b)? }),
+        ("O", [ind, compl, n]) => Some(Insn::Output { ind: *ind, lit: lit(*compl, *n)? }),
+        ("U", [c, compl, n]) => Some(Insn::Union { c: *c, lit: lit(*compl, *n)? }),
+        _ => None,
+    }
+}
+
+impl Trace {
+    pub fn parse(contents: &str) -> Result<Trace, TraceError> {
+        let mut trace = Trace::default();
+        let mut temp: Vec<(usize, Insn)> = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line == "COMMIT" {
+                for (l, insn) in temp.drain(..) {
+                    trace.lines.push(l);
+                    trace.insns.push(insn);
+                }
+            } else if line == "FORGET" {
+                if !trace.insns.is_empty() {
+                    trace.forgotten += temp.len();
+                    temp.clear();
+                }
+            } else if line.starts_with("COM") || line.is_empty() {
+                continue;
+            } else {
+                let insn = parse_insn(line).ok_or_else(|| TraceError { line: i + 1, msg: format!("malformed instruction \"{}\"", line) })?;
+                temp.push((i + 1, insn));
+            }
+        }
+        for (l, insn) in temp {
+            trace.lines.push(l);
+            trace.insns.push(insn);
+        }
+        Ok(trace)
+    }
+
+    fn is_step(insn: &Insn, outputs: &mut usize) -> bool {
+        match insn {
+            Insn::Gate { .. } => false,
+            Insn::Output { ind, .. } if *ind >= *outputs => {
+                *outputs = ind + 1;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Number of rewrite steps.
+    pub fn steps(&self) -> usize {
+        let mut outputs = 0;
+        self.insns.iter().filter(|insn| Self::is_step(insn, &mut outputs)).count()
+    }
+
+    /// The trace with only the rewrite steps in `range` (numbered from 0); gates and the
+    /// first O of every output are kept.
+    pub fn select(&self, range: Range<usize>) -> Trace {
+        let mut selected = Trace { forgotten: self.forgotten, ..Trace::default() };
+        let (mut outputs, mut step) = (0, 0);
+        for (insn, line) in self.insns.iter().zip(&self.lines) {
+            if Self::is_step(insn, &mut outputs) {
+                step += 1;
+                if !range.contains(&(step - 1)) {
+                    continue;
+                }
+            }
+            selected.insns.push(*insn);
+            selected.lines.push(*line);
+        }
+        selected
+    }
+
+    /// Undefined node references, redefined nodes, unions of a node with its own complement
+    /// and outputs written out of order, for a circuit with `num_pis` inputs.
+    pub fn validate(&self, num_pis: usize) -> Vec<TraceError> {
+        let mut errors = Vec::new();
+        let mut defined: HashSet<usize> = (0..=num_pis).collect();
+        let mut outputs = 0;
+        for (insn, line) in self.insns.iter().zip(&self.lines) {
+            let mut error = |msg: String| errors.push(TraceError { line: *line, msg: format!("{}: {}", insn, msg) });
+            let refs: Vec<usize> = match insn {
+                Insn::Gate { a, b, .. } => vec![a.node, b.node],
+                Insn::Output { lit, .. } => vec![lit.node],
+                Insn::Union { c, lit } => vec![*c, lit.node],
+            };
+            for node in refs.iter().filter(|n| !defined.contains(n)) {
+                error(format!("node {} is not defined", node));
+            }
+            match insn {
+                Insn::Gate { n, .. } => {
+                    if !defined.insert(*n) {
+                        error(format!("node {} is defined again", n));
+                    }
+                }
+                Insn::Output { ind, .. } => {
+                    if *ind > outputs {
+                        error(format!("output {} is written before output {}", ind, outputs));
+                    }
+                    outputs = outputs.max(ind + 1);
+                }
+                Insn::Union { c, lit } => {
+                    if *c == lit.node && lit.compl {
+                        error(format!("node {} is united with its own complement", c));
+                    }
+                }
+            }
+        }
+        errors
+    }
+
+    /// Prints instruction counts and, for every output, its replacements and the unions in the
+    /// fan-in of its last version.
+    pub fn print_stats(&self, outnodes: &str) {
+        let count = |f: fn(&Insn) -> bool| self.insns.iter().filter(|i| f(i)).count();
+        println!(
+            "trace: {} instructions ({} forgotten); {} XOR, {} AND, {} O, {} U; {} rewrite steps",
+            self.insns.len(),
+            self.forgotten,
+            count(|i| matches!(i, Insn::Gate { xor: true, .. })),
+            count(|i| matches!(i, Insn::Gate { xor: false, .. })),
+            count(|i| matches!(i, Insn::Output { .. })),
+            count(|i| matches!(i, Insn::Union { .. })),
+            self.steps()
+        );
+
+        let mut gates: HashMap<usize, [usize; 2]> = HashMap::new();
+        let mut unions: HashMap<usize, usize> = HashMap::new();
+        let mut outputs: Vec<(usize, usize)> = Vec::new();
+        for insn in &self.insns {
+            match insn {
+                Insn::Gate { n, a, b, .. } => {
+                    gates.insert(*n, [a.node, b.node]);
+                }
+                Insn::Output { ind, lit } if *ind >= outputs.len() => outputs.push((lit.node, 0)),
+                Insn::Output { ind, lit } => outputs[*ind] = (lit.node, outputs[*ind].1 + 1),
+                Insn::Union { c, .. } => *unions.entry(*c).or_default() += 1,
+            }
+        }
+        for (name, (root, replacements)) in outnodes.split(" ").zip(outputs) {
+            let mut seen: HashSet<usize> = HashSet::new();
+            let mut stack = vec![root];
+            while let Some(node) = stack.pop() {
+                if seen.insert(node) {
+                    stack.extend(gates.get(&node).into_iter().flatten());
+                }
+            }
+            let in_cone: usize = seen.iter().filter_map(|n| unions.get(n)).sum();
+            println!("trace output {}: {} replacements, {} unions in its cone", name, replacements, in_cone);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(contents: &str, num_pis: usize) -> Vec<String> {
+        Trace::parse(contents).unwrap().validate(num_pis).iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn undefined_references_are_reported() {
+        assert_eq!(
+            messages("A 3 0 1 0 4\nU 2 0 5\n", 2),
+            vec!["trace line 1: A 3 0 1 0 4: node 4 is not defined", "trace line 2: U 2 0 5: node 5 is not defined"]
+        );
+    }
+
+    #[test]
+    fn redefined_nodes_are_reported() {
+        assert_eq!(messages("A 3 0 1 0 2\nX 3 0 1 1 2\n", 2), vec!["trace line 2: X 3 0 1 1 2: node 3 is defined again"]);
+        assert_eq!(messages("A 2 0 1 0 1\n", 2), vec!["trace line 1: A 2 0 1 0 1: node 2 is defined again"]);
+    }
+
+    #[test]
+    fn self_complement_unions_are_reported() {
+        assert_eq!(messages("A 3 0 1 0 2\nU 3 0 3\nU 3 1 3\n", 2), vec!["trace line 3: U 3 1 3: node 3 is united with its own complement"]);
+    }
+
+    #[test]
+    fn forget_drops_instructions_since_the_last_commit() {
+        // nothing is committed yet, so the first FORGET is ignored
+        let trace = Trace::parse("A 3 0 1 0 2\nFORGET\nCOMMIT\nU 3 0 1\nFORGET\nO 0 0 3\n").unwrap();
+        let lit = |node| Lit { node, compl: false };
+        assert_eq!(trace.insns, vec![Insn::Gate { n: 3, xor: false, a: lit(1), b: lit(2) }, Insn::Output { ind: 0, lit: lit(3) }]);
+        assert_eq!(trace.lines, vec![1, 6]);
+        assert_eq!(trace.forgotten, 1);
+    }
+
+    #[test]
+    fn select_numbers_rewrite_steps_from_zero() {
+        // steps: U 3 (0), O 0 replacing output 0 (1), U 4 (2); gates and first outputs are kept
+        let trace = Trace::parse("A 3 0 1 0 2\nO 0 0 3\nU 3 0 1\nX 4 0 1 0 2\nO 0 1 4\nO 1 0 4\nU 4 1 3\n").unwrap();
+        assert_eq!(trace.steps(), 3);
+        let lines = |range| trace.select(range).lines;
+        assert_eq!(lines(0..1), vec![1, 2, 3, 4, 6]);
+        assert_eq!(lines(1..3), vec![1, 2, 4, 5, 6, 7]);
+        assert_eq!(lines(3..3), vec![1, 2, 4, 6]);
+        assert_eq!(trace.select(0..3).insns, trace.insns);
+    }
+}